@@ -9,6 +9,9 @@
 //! let afio_periph = afio.get_peripherals(); 
 //! 
 //! let remap = afio_periph.usart1.set_remapped();
+//! 
+//! // release PA15, PB3 and PB4 for use by SPI1
+//! let swj = afio_periph.swj.release_jtag();
 //! ```
  
 #[allow(unused_imports)]
@@ -17,10 +20,26 @@ use common;
 use rcc;
 use rcc::RccPeripheral;
 use stm32f103xx::{AFIO, USART1, USART2, I2C1, SPI1};
+use stm32f103xx::afio::mapr;
 
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 type_states!(IsRemapped, (NotConfigured, NotRemapped, Remapped));
+type_states!(IsJtagReleased, (JtagEnabled, JtagReleased));
+
+/// Last value written to `SWJ_CFG`
+/// 
+/// The field is write only and always reads as zero, so it has to be restored
+/// whenever `MAPR` is modified, otherwise JTAG would be enabled again.
+static SWJ_CFG: AtomicUsize = AtomicUsize::new(0b000);
+
+#[inline(always)]
+fn modify_mapr<F>(afio: &AFIO, f: F)
+where F: for<'w> FnOnce(&'w mut mapr::W) -> &'w mut mapr::W {
+    let swj_cfg = SWJ_CFG.load(Ordering::Relaxed) as u8;
+    afio.mapr.modify(|_, w| unsafe { f(w).swj_cfg().bits(swj_cfg) });
+}
 
 pub struct AfioPeripheral<'a, P, R>(pub &'a AFIO, PhantomData<P>, PhantomData<R>)
 where R: IsRemapped;
@@ -36,7 +55,7 @@ macro_rules! peripheral_macro  {
 
             #[inline(always)]
             pub fn set_remapped(self) -> AfioPeripheral<'a, $periph, Remapped> {
-                self.0.mapr.modify(|_, w| { w.$remap_bit().set_bit() });
+                modify_mapr(self.0, |w| w.$remap_bit().set_bit());
                 AfioPeripheral(self.0, self.1, PhantomData)
             }
         }
@@ -48,6 +67,23 @@ peripheral_macro!(USART2, usart2_remap);
 peripheral_macro!(I2C1, i2c1_remap);
 peripheral_macro!(SPI1, spi1_remap);
 
+/// Serial wire / JTAG debug port configuration
+pub struct Swj<'a, J>(pub &'a AFIO, PhantomData<J>)
+where J: IsJtagReleased;
+
+impl<'a> Swj<'a, JtagEnabled> {
+    /// Disables JTAG while keeping serial wire debugging enabled
+    /// 
+    /// This releases PA15 (JTDI), PB3 (JTDO) and PB4 (NJTRST), which are required 
+    /// by remapped peripherals such as SPI1.
+    #[inline(always)]
+    pub fn release_jtag(self) -> Swj<'a, JtagReleased> {
+        SWJ_CFG.store(0b010, Ordering::Relaxed);
+        modify_mapr(self.0, |w| w);
+        Swj(self.0, PhantomData)
+    }
+}
+
 pub struct Afio<'a>(pub &'a AFIO);
 
 impl <'a> Afio<'a> {
@@ -62,6 +98,7 @@ macro_rules! peripherals {
             $( 
                 pub $name : AfioPeripheral<'a, $periph, NotConfigured>,
             )*
+            pub swj : Swj<'a, JtagEnabled>,
         }
 
         impl <'a> Afio<'a> {
@@ -71,6 +108,7 @@ macro_rules! peripherals {
                     $(
                         $name: AfioPeripheral(self.0, PhantomData, PhantomData),
                     )*
+                    swj: Swj(self.0, PhantomData),
                 }
             }
         }
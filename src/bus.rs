@@ -0,0 +1,250 @@
+//! Shared bus access
+//! 
+//! Allows several device drivers to share a single bus. Each device gets its own 
+//! proxy which implements the blocking bus traits and takes care of switching 
+//! the bus configuration and the chip select signal.
+//! 
+//! # Example
+//! 
+//! ```
+//! let spi1 = Spi::new(
+//!     &dp.SPI1, 
+//!     Spi::ports_normal(pa4, pa5, pa6, pa7, afio_periph.spi1.set_not_remapped()),
+//!     rcc.peripherals.spi1.enable(),
+//!     1.mhz().into(),
+//!     clocks,
+//! );
+//! 
+//! let bus = SpiBus::new(spi1, clocks);
+//! 
+//! let pb0 = pinsb.0.set_output_50MHz().set_output_push_pull();
+//! let pb1 = pinsb.1.set_output_50MHz().set_output_push_pull();
+//! 
+//! let mut flash = bus.device(pb0, hal::spi::MODE_0, 8.mhz());
+//! let mut display = bus.device(pb1, hal::spi::MODE_3, 1.mhz());
+//! 
+//! flash.write(&[0x06]).unwrap();
+//! display.write(&[0x2C, 0x00, 0x1F]).unwrap();
+//! ```
+//! 
+//! I2C devices are selected by their address, the proxies of an `I2cBus` can be
+//! cloned and handed to any number of drivers:
+//! 
+//! ```
+//! let bus = I2cBus::new(i2c1);
+//! 
+//! let mut imu = Imu::new(bus.proxy());
+//! let mut eeprom = Eeprom::new(bus.proxy());
+//! ```
+
+#[allow(unused_imports)]
+use common;
+
+use core::any::Any;
+use core::cell::{Cell, RefCell};
+
+use hal;
+
+use afio::IsRemapped;
+use gpio::{GpioPin, GPIO, Pins, PinNr, PinMode, PinOutput, PinCnf0};
+use i2c::{self, I2c, I2C};
+use rcc::Clocks;
+use spi::{self, Spi, SPI, Master};
+use time::Hertz;
+
+/// Bus configuration of a single SPI device
+#[derive(Clone, Copy, PartialEq)]
+struct SpiConfig {
+    cpol: bool,
+    cpha: bool,
+    br: u8,
+}
+
+/// An SPI bus shared by several devices
+/// 
+/// The bus is only reconfigured if a device with a different configuration than
+/// the previously used device starts a transaction.
+pub struct SpiBus<'a, S, R>
+where S: Any + SPI, R: IsRemapped {
+    spi: RefCell<Spi<'a, S, R, Master>>,
+    clocks: Clocks,
+    config: Cell<Option<SpiConfig>>,
+}
+
+impl<'a, S, R> SpiBus<'a, S, R>
+where S: Any + SPI, R: IsRemapped {
+    pub fn new(spi: Spi<'a, S, R, Master>, clocks: Clocks) -> Self {
+        SpiBus {
+            spi: RefCell::new(spi),
+            clocks,
+            config: Cell::new(None),
+        }
+    }
+
+    /// Creates a proxy for a device on this bus
+    /// 
+    /// # Arguments
+    /// - `cs`: The chip select pin of the device, which is driven high until the device is used.
+    /// - `mode`: The clock polarity and phase used by the device.
+    /// - `freq`: The maximum clock frequency supported by the device.
+    pub fn device<'b, G, P, M, F>(&'b self, mut cs: GpioPin<G, P, M, PinCnf0>, mode: hal::spi::Mode, freq: F) 
+        -> SpiDevice<'b, 'a, S, R, G, P, M> 
+        where G: GPIO, P: Pins + PinNr, M: PinOutput + PinMode, F: Into<Hertz> {
+        cs.set(true);
+
+        SpiDevice {
+            bus: self,
+            cs,
+            config: SpiConfig {
+                cpol: mode.polarity == hal::spi::Polarity::IdleHigh,
+                cpha: mode.phase == hal::spi::Phase::CaptureOnSecondTransition,
+                br: spi::baud_rate_bits(S::clock(&self.clocks), freq.into()),
+            },
+        }
+    }
+
+    /// Releases the underlying `Spi`
+    pub fn free(self) -> Spi<'a, S, R, Master> {
+        self.spi.into_inner()
+    }
+}
+
+/// A single device on a shared SPI bus
+pub struct SpiDevice<'b, 'a: 'b, S, R, G, P, M>
+where S: Any + SPI, R: IsRemapped, G: GPIO, P: Pins + PinNr, M: PinOutput + PinMode {
+    bus: &'b SpiBus<'a, S, R>,
+    cs: GpioPin<G, P, M, PinCnf0>,
+    config: SpiConfig,
+}
+
+impl<'b, 'a, S, R, G, P, M> SpiDevice<'b, 'a, S, R, G, P, M>
+where S: Any + SPI, R: IsRemapped, G: GPIO, P: Pins + PinNr, M: PinOutput + PinMode {
+    /// Runs `f` with exclusive access to the bus while the chip select is asserted
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if called while another transaction on the same bus is in progress.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T, spi::Error> 
+        where F: FnOnce(&mut Spi<'a, S, R, Master>) -> Result<T, spi::Error> {
+        let mut spi = self.bus.spi.borrow_mut();
+
+        if self.bus.config.get() != Some(self.config) {
+            spi.configure(self.config.cpol, self.config.cpha, self.config.br);
+            self.bus.config.set(Some(self.config));
+        } else {
+            spi.enable();
+        }
+
+        self.cs.set(false);
+        let res = f(&mut *spi);
+        spi.wait_idle();
+        self.cs.set(true);
+
+        res
+    }
+
+    /// Releases the chip select pin
+    pub fn free(self) -> GpioPin<G, P, M, PinCnf0> {
+        self.cs
+    }
+}
+
+impl<'b, 'a, S, R, G, P, M> hal::blocking::spi::Transfer<u8> for SpiDevice<'b, 'a, S, R, G, P, M>
+where S: Any + SPI, R: IsRemapped, G: GPIO, P: Pins + PinNr, M: PinOutput + PinMode {
+    type Error = spi::Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], spi::Error> {
+        self.transaction(move |spi| hal::blocking::spi::Transfer::transfer(spi, words))
+    }
+}
+
+impl<'b, 'a, S, R, G, P, M> hal::blocking::spi::Write<u8> for SpiDevice<'b, 'a, S, R, G, P, M>
+where S: Any + SPI, R: IsRemapped, G: GPIO, P: Pins + PinNr, M: PinOutput + PinMode {
+    type Error = spi::Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), spi::Error> {
+        self.transaction(|spi| hal::blocking::spi::Write::write(spi, words))
+    }
+}
+
+/// An I2C bus shared by several devices
+pub struct I2cBus<S, R>
+where S: Any + I2C, R: IsRemapped {
+    i2c: RefCell<I2c<S, R>>,
+}
+
+impl<S, R> I2cBus<S, R>
+where S: Any + I2C, R: IsRemapped {
+    pub fn new(i2c: I2c<S, R>) -> Self {
+        I2cBus {
+            i2c: RefCell::new(i2c),
+        }
+    }
+
+    /// Creates a proxy giving access to the bus
+    pub fn proxy<'b>(&'b self) -> I2cProxy<'b, S, R> {
+        I2cProxy {
+            bus: self,
+        }
+    }
+
+    /// Releases the underlying `I2c`
+    pub fn free(self) -> I2c<S, R> {
+        self.i2c.into_inner()
+    }
+}
+
+/// A handle to a shared I2C bus
+pub struct I2cProxy<'b, S, R>
+where S: Any + I2C + 'b, R: IsRemapped + 'b {
+    bus: &'b I2cBus<S, R>,
+}
+
+impl<'b, S, R> Clone for I2cProxy<'b, S, R>
+where S: Any + I2C, R: IsRemapped {
+    fn clone(&self) -> Self {
+        I2cProxy {
+            bus: self.bus,
+        }
+    }
+}
+
+impl<'b, S, R> I2cProxy<'b, S, R>
+where S: Any + I2C, R: IsRemapped {
+    /// Runs `f` with exclusive access to the bus
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if called while another transaction on the same bus is in progress.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T, i2c::Error> 
+        where F: FnOnce(&mut I2c<S, R>) -> Result<T, i2c::Error> {
+        f(&mut *self.bus.i2c.borrow_mut())
+    }
+}
+
+impl<'b, S, R> hal::blocking::i2c::Write for I2cProxy<'b, S, R>
+where S: Any + I2C, R: IsRemapped {
+    type Error = i2c::Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), i2c::Error> {
+        self.transaction(|i2c| hal::blocking::i2c::Write::write(i2c, addr, bytes))
+    }
+}
+
+impl<'b, S, R> hal::blocking::i2c::Read for I2cProxy<'b, S, R>
+where S: Any + I2C, R: IsRemapped {
+    type Error = i2c::Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.transaction(|i2c| hal::blocking::i2c::Read::read(i2c, addr, buffer))
+    }
+}
+
+impl<'b, S, R> hal::blocking::i2c::WriteRead for I2cProxy<'b, S, R>
+where S: Any + I2C, R: IsRemapped {
+    type Error = i2c::Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.transaction(|i2c| hal::blocking::i2c::WriteRead::write_read(i2c, addr, bytes, buffer))
+    }
+}
@@ -1,3 +1,5 @@
+use core::ptr;
+
 
 macro_rules! type_states {
     ($type:ident, ($($id:ident),*)) => {
@@ -18,4 +20,20 @@ macro_rules! type_group {
             impl $type for $id {}
         )*
     };
-}
\ No newline at end of file
+}
+
+/// Writes `value` to the register `reg` using an access of the width of `T`, e.g.
+/// a single byte to the data register of the SPI, as the svd2rust registers only 
+/// provide 32-bit writes
+/// 
+/// The pointer is derived from a shared reference. This is sound as the registers
+/// are `VolatileCell`s, but has to be done through a raw pointer argument so the
+/// `invalid_reference_casting` lint does not reject it.
+/// 
+/// # Safety
+/// 
+/// `reg` has to point to a register which accepts accesses of the width of `T`.
+#[inline(always)]
+pub unsafe fn write_register<R, T>(reg: *const R, value: T) {
+    ptr::write_volatile(reg as *mut T, value)
+}
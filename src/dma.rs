@@ -0,0 +1,316 @@
+//! Direct Memory Access
+//! 
+//! Provides the channels of the DMA controllers, which are used by the 
+//! peripheral drivers to move data without involving the processor. DMA2 is only
+//! available on high-density devices (`high-density` feature).
+//! 
+//! # Example
+//! 
+//! ```
+//! let dma1 = Dma::new(dp.DMA1);
+//! let channels = dma1.get_channels(rcc.peripherals.dma1.enable());
+//! 
+//! let spi1 = spi1.with_dma(channels.c2, channels.c3);
+//! ```
+
+#[allow(unused_imports)]
+use common;
+
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+use rcc;
+use stm32f103xx::{dma1, DMA1};
+#[cfg(feature = "high-density")]
+use stm32f103xx::DMA2;
+
+/// DMA controller that can be used with the `Dma` abstraction
+/// 
+/// # Safety
+/// 
+/// `ptr` has to return the address of the register block of the controller.
+pub unsafe trait DMA: Deref<Target = dma1::RegisterBlock> {
+    fn ptr() -> *const dma1::RegisterBlock;
+}
+
+unsafe impl DMA for DMA1 {
+    fn ptr() -> *const dma1::RegisterBlock { DMA1::ptr() }
+}
+
+#[cfg(feature = "high-density")]
+unsafe impl DMA for DMA2 {
+    fn ptr() -> *const dma1::RegisterBlock { DMA2::ptr() }
+}
+
+type_states!(Channels, (C1, C2, C3, C4, C5, C6, C7));
+
+/// Number and registers of a DMA channel
+pub trait ChannelNr {
+    fn nr() -> u8;
+    #[doc(hidden)]
+    fn configure(dma: &dma1::RegisterBlock, par: u32, mar: u32, len: u16, config: Config);
+    #[doc(hidden)]
+    fn set_enabled(dma: &dma1::RegisterBlock, enabled: bool);
+    #[doc(hidden)]
+    fn set_listen(dma: &dma1::RegisterBlock, enabled: bool);
+    #[doc(hidden)]
+    fn set_listen_half(dma: &dma1::RegisterBlock, enabled: bool);
+    #[doc(hidden)]
+    fn remaining(dma: &dma1::RegisterBlock) -> u16;
+}
+
+macro_rules! channel {
+    ($channel:ident, $nr:expr, $ccr:ident, $cndtr:ident, $cpar:ident, $cmar:ident) => {
+        impl ChannelNr for $channel {
+            #[inline(always)]
+            fn nr() -> u8 { $nr }
+
+            fn configure(dma: &dma1::RegisterBlock, par: u32, mar: u32, len: u16, config: Config) {
+                dma.$cpar.write(|w| unsafe { w.bits(par) });
+                dma.$cmar.write(|w| unsafe { w.bits(mar) });
+                dma.$cndtr.write(|w| unsafe { w.bits(len as u32) });
+
+                dma.$ccr.write(|w| unsafe {
+                    w.mem2mem()
+                    .clear_bit()
+                    .pl()
+                    .bits(0b01)
+                    .msize()
+                    .bits(config.size.bits())
+                    .psize()
+                    .bits(config.size.bits())
+                    .minc()
+                    .bit(config.minc)
+                    .pinc()
+                    .clear_bit()
+                    .circ()
+                    .bit(config.circular)
+                    .dir()
+                    .bit(config.dir == Direction::MemoryToPeripheral)
+                });
+            }
+
+            #[inline(always)]
+            fn set_enabled(dma: &dma1::RegisterBlock, enabled: bool) {
+                dma.$ccr.modify(|_, w| w.en().bit(enabled));
+            }
+
+            #[inline(always)]
+            fn set_listen(dma: &dma1::RegisterBlock, enabled: bool) {
+                dma.$ccr.modify(|_, w| w.tcie().bit(enabled).teie().bit(enabled));
+            }
+
+            #[inline(always)]
+            fn set_listen_half(dma: &dma1::RegisterBlock, enabled: bool) {
+                dma.$ccr.modify(|_, w| w.htie().bit(enabled));
+            }
+
+            #[inline(always)]
+            fn remaining(dma: &dma1::RegisterBlock) -> u16 {
+                dma.$cndtr.read().bits() as u16
+            }
+        }
+    }
+}
+
+channel!(C1, 1, ccr1, cndtr1, cpar1, cmar1);
+channel!(C2, 2, ccr2, cndtr2, cpar2, cmar2);
+channel!(C3, 3, ccr3, cndtr3, cpar3, cmar3);
+channel!(C4, 4, ccr4, cndtr4, cpar4, cmar4);
+channel!(C5, 5, ccr5, cndtr5, cpar5, cmar5);
+channel!(C6, 6, ccr6, cndtr6, cpar6, cmar6);
+channel!(C7, 7, ccr7, cndtr7, cpar7, cmar7);
+
+/// Transfer direction
+#[derive(PartialEq, Clone, Copy)]
+pub enum Direction {
+    /// Read from the peripheral and write to memory
+    PeripheralToMemory,
+    /// Read from memory and write to the peripheral
+    MemoryToPeripheral,
+}
+
+/// Size of a single data item
+#[derive(PartialEq, Clone, Copy)]
+pub enum DataSize {
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+impl DataSize {
+    #[inline(always)]
+    fn bits(self) -> u8 {
+        match self {
+            DataSize::Bits8 => 0b00,
+            DataSize::Bits16 => 0b01,
+            DataSize::Bits32 => 0b10,
+        }
+    }
+}
+
+/// Configuration of a transfer
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Transfer direction
+    pub dir: Direction,
+    /// Whether the memory address is incremented after each item
+    pub minc: bool,
+    /// Size of the items in memory and in the peripheral
+    pub size: DataSize,
+    /// Whether the transfer restarts from the beginning of the buffer once it has
+    /// completed, until the channel is stopped
+    pub circular: bool,
+}
+
+/// Interrupt flags of a single channel, stored in `ISR` and cleared through `IFCR`
+const FLAG_GIF: u32 = 0b0001;
+const FLAG_TCIF: u32 = 0b0010;
+const FLAG_HTIF: u32 = 0b0100;
+const FLAG_TEIF: u32 = 0b1000;
+
+pub struct DmaChannel<C, D = DMA1>(PhantomData<(C, D)>)
+where C: Channels, D: DMA;
+
+impl<C, D> DmaChannel<C, D>
+where C: Channels + ChannelNr, D: DMA {
+    #[inline(always)]
+    fn dma<'a>(&self) -> &'a dma1::RegisterBlock {
+        unsafe { &(*D::ptr()) }
+    }
+
+    #[inline(always)]
+    fn flags(&self) -> u32 {
+        (self.dma().isr.read().bits() >> (4 * (C::nr() - 1))) & 0b1111
+    }
+
+    /// Configures and starts a transfer of `len` bytes between a peripheral data 
+    /// register and memory
+    /// 
+    /// # Arguments
+    /// - `par`: The address of the peripheral data register.
+    /// - `mar`: The memory address.
+    /// - `minc`: Whether the memory address is incremented after each byte.
+    pub fn start(&mut self, par: u32, mar: u32, len: usize, dir: Direction, minc: bool) {
+        self.start_with(par, mar, len, Config {
+            dir,
+            minc,
+            size: DataSize::Bits8,
+            circular: false,
+        })
+    }
+
+    /// Configures and starts a transfer of `len` items using the given configuration
+    pub fn start_with(&mut self, par: u32, mar: u32, len: usize, config: Config) {
+        assert!(len <= 0xFFFF);
+
+        self.stop();
+
+        C::configure(self.dma(), par, mar, len as u16, config);
+        C::set_enabled(self.dma(), true);
+    }
+
+    /// Disables the channel and clears all of its interrupt flags
+    pub fn stop(&mut self) {
+        C::set_enabled(self.dma(), false);
+        self.dma().ifcr.write(|w| unsafe { w.bits(0b1111 << (4 * (C::nr() - 1))) });
+    }
+
+    /// Enables the transfer complete and transfer error interrupts
+    pub fn listen(&mut self) {
+        C::set_listen(self.dma(), true);
+    }
+
+    /// Disables the transfer complete and transfer error interrupts
+    pub fn unlisten(&mut self) {
+        C::set_listen(self.dma(), false);
+    }
+
+    /// Returns `true` once all bytes have been transferred
+    pub fn is_complete(&self) -> bool {
+        self.flags() & FLAG_TCIF != 0
+    }
+
+    /// Returns `true` once half of the bytes have been transferred
+    pub fn is_half_complete(&self) -> bool {
+        self.flags() & FLAG_HTIF != 0
+    }
+
+    /// Clears the transfer complete and half transfer flags
+    /// 
+    /// Used in circular mode to wait for the next half of the buffer.
+    pub fn clear_complete(&mut self) {
+        self.dma().ifcr.write(|w| unsafe { w.bits((FLAG_TCIF | FLAG_HTIF | FLAG_GIF) << (4 * (C::nr() - 1))) });
+    }
+
+    /// Enables the half transfer interrupt
+    pub fn listen_half(&mut self) {
+        C::set_listen_half(self.dma(), true);
+    }
+
+    /// Returns `true` if a bus error occurred during the transfer
+    /// 
+    /// The channel is disabled by the hardware in this case.
+    pub fn has_error(&self) -> bool {
+        self.flags() & FLAG_TEIF != 0
+    }
+
+    /// Returns `true` if any event occurred on this channel
+    pub fn has_event(&self) -> bool {
+        self.flags() & FLAG_GIF != 0
+    }
+
+    /// Returns the number of bytes which still have to be transferred
+    pub fn remaining(&self) -> u16 {
+        C::remaining(self.dma())
+    }
+}
+
+macro_rules! channels {
+    ($dma:ident, $channels:ident, $(($channel:ident, $name:ident)),* ) => {
+        pub struct $channels {
+            $( 
+                pub $name : DmaChannel<$channel, $dma>,
+            )*
+        }
+
+        impl Dma<$dma> {
+            #[inline(always)]
+            pub fn get_channels(self, _rcc: rcc::RccPeripheral<$dma, rcc::Enabled>) -> $channels {
+                $channels {
+                    $(
+                        $name: DmaChannel(PhantomData),
+                    )*
+                }
+            }
+        }
+    }
+}
+
+pub struct Dma<D>(PhantomData<D>)
+where D: DMA;
+
+impl<D> Dma<D> where D: DMA {
+    pub fn new(_dma: D) -> Self {
+        Dma(PhantomData)
+    }
+}
+
+channels!(DMA1, DmaChannels,
+    (C1, c1),
+    (C2, c2),
+    (C3, c3),
+    (C4, c4),
+    (C5, c5),
+    (C6, c6),
+    (C7, c7)
+);
+
+#[cfg(feature = "high-density")]
+channels!(DMA2, Dma2Channels,
+    (C1, c1),
+    (C2, c2),
+    (C3, c3),
+    (C4, c4),
+    (C5, c5)
+);
@@ -12,6 +12,74 @@
 //! 
 //! - SCL = PB10
 //! - SDA = PB11
+//! 
+//! # Slave mode
+//! 
+//! In slave mode the peripheral responds to up to two own addresses and optionally
+//! to the general call address. Bus events are reported by `event`, which should 
+//! be called from the I2C event interrupt handler.
+//! 
+//! ```
+//! let mut i2c = I2c::new_slave(dp.I2C1, ports, rcc.peripherals.i2c1.enable(), clocks, 
+//!     SlaveConfig { address: 0x42, address2: None, general_call: false });
+//! i2c.listen();
+//! i2c.listen_buffer();
+//! 
+//! // I2C1_EV interrupt
+//! loop {
+//!     match i2c.event() {
+//!         Ok(SlaveEvent::AddressMatch { read, .. }) => { /* start of a transfer */ },
+//!         Ok(SlaveEvent::Received(byte)) => { /* byte written by the master */ },
+//!         Ok(SlaveEvent::ByteRequested) => i2c.respond(0x00),
+//!         Ok(SlaveEvent::Stop) => { /* end of the transfer */ },
+//!         Err(nb::Error::WouldBlock) => break,
+//!         Err(nb::Error::Other(_)) => { /* error */ },
+//!     }
+//! }
+//! ```
+//! 
+//! # Interrupt driven transactions
+//! 
+//! A transaction started using `write_async`, `read_async` or `write_read_async`
+//! is progressed from the I2C event and error interrupt handlers, without blocking
+//! the processor while the bus is busy.
+//! 
+//! ```
+//! static BUFFER: [u8; 2] = [0; 2];
+//! let mut transaction = i2c.write_read_async(0x48, &COMMAND, &mut BUFFER);
+//! 
+//! // I2C1_EV interrupt
+//! if transaction.on_event() { /* completed */ }
+//! // I2C1_ER interrupt
+//! if transaction.on_error() { /* completed */ }
+//! 
+//! let result = block!(transaction.poll());
+//! let (bytes, buffer, i2c) = transaction.free();
+//! ```
+//! 
+//! # DMA
+//! 
+//! The data of a transfer can be moved by the DMA, I2C1 uses DMA1 channels 6 (TX)
+//! and 7 (RX), I2C2 uses DMA1 channels 4 (TX) and 5 (RX). The start signal and the
+//! address are still sent by the processor.
+//! 
+//! ```
+//! let i2c = i2c.with_dma(channels.c6, channels.c7);
+//! let transfer = i2c.read(0x50, buffer);
+//! // ...
+//! let (result, buffer, i2c) = transfer.wait();
+//! ```
+//! 
+//! # Addresses
+//! 
+//! The `hal::blocking::i2c` traits take 7-bit addresses, the read / write bit is
+//! added by the driver. Devices with 10-bit addresses are accessed using 
+//! `write_to`, `read_from` and `write_read_to`, which take an `Address`:
+//! 
+//! ```
+//! i2c.write_to(Address::Ten(0x2A5), &[0x00, 0x10])?;
+//! i2c.write_read_to(Address::Seven(0x50), &[0x00], &mut buffer)?;
+//! ```
 
 #[allow(unused_imports)]
 use common;
@@ -21,27 +89,48 @@ use core::any::{Any};
 use core::fmt;
 use core::ops::Deref;
 use core::marker::PhantomData;
+use core::sync::atomic::{self, Ordering};
 
-use stm32f103xx::{GPIOB, I2C1, I2C2, i2c1};
+use stm32f103xx::{GPIOB, I2C1, I2C2, DMA1, i2c1};
 
 #[macro_use(block)]
 use nb;
 
+use cortex_m::{asm, interrupt};
+
 use afio::{AfioPeripheral, IsRemapped, Remapped, NotRemapped};
-use gpio::{GpioPin, Pin6, Pin7, Pin8, Pin9, PinCnf3, PinOutput, PinMode};
-use rcc::Clocks;
-use time::Hertz;
+use dma::{self, DmaChannel, Channels, ChannelNr, C4, C5, C6, C7};
+use gpio::{GpioPin, Pin6, Pin7, Pin8, Pin9, Pin10, Pin11, PinCnf3, PinOutput, PinMode};
+use rcc;
+use rcc::{Clocks, RccPeripheral};
+use time::{Hertz, MonoTimer};
 use hal::blocking::i2c as hal_i2c;
 
 
 pub unsafe trait I2C: Deref<Target = i2c1::RegisterBlock> {
+    /// DMA1 channel used for transmission
+    type DmaTx: Channels + ChannelNr;
+    /// DMA1 channel used for reception
+    type DmaRx: Channels + ChannelNr;
+
+    /// Resets all registers of the peripheral through the RCC
+    fn reset(rcc: RccPeripheral<Self, rcc::Enabled>) -> RccPeripheral<Self, rcc::Enabled> where Self: Sized;
 }
 
 unsafe impl I2C for I2C1 {
+    type DmaTx = C6;
+    type DmaRx = C7;
 
+    #[inline(always)]
+    fn reset(rcc: RccPeripheral<Self, rcc::Enabled>) -> RccPeripheral<Self, rcc::Enabled> { rcc.reset() }
 }
 
 unsafe impl I2C for I2C2 {
+    type DmaTx = C4;
+    type DmaRx = C5;
+
+    #[inline(always)]
+    fn reset(rcc: RccPeripheral<Self, rcc::Enabled>) -> RccPeripheral<Self, rcc::Enabled> { rcc.reset() }
 }
 
 
@@ -70,6 +159,21 @@ pub enum Error {
     OVR,
     /// Bus Error
     BERR,
+    /// The operation did not complete within the timeout set using `set_timeout`
+    /// 
+    /// The bus is most likely stalled and can be recovered using `recover_bus`.
+    TimedOut,
+    /// Packet Error Checking failure
+    /// 
+    /// The received PEC does not match the calculated PEC (SMBus).
+    PEC,
+    /// The buffer to read into is empty, or the length reported by the slave does 
+    /// not fit into the buffer (SMBus block read)
+    InvalidLength,
+    /// DMA transfer error
+    TransferError,
+    /// The transaction has been aborted before it completed
+    Aborted,
 }
 
 impl fmt::Display for Error {
@@ -80,16 +184,66 @@ impl fmt::Display for Error {
             Error::AF => write!(f,"AF"),
             Error::ARLO => write!(f, "ARLO"),
             Error::OVR => write!(f, "OVR"),
-            Error::BERR => write!(f, "BERR")
+            Error::BERR => write!(f, "BERR"),
+            Error::TimedOut => write!(f, "TimedOut"),
+            Error::PEC => write!(f, "PEC"),
+            Error::InvalidLength => write!(f, "InvalidLength"),
+            Error::TransferError => write!(f, "TransferError"),
+            Error::Aborted => write!(f, "Aborted"),
         }
     }
 }
 
-pub struct I2c<S, R> where S: Any + I2C, R: IsRemapped {
+type_states!(I2cMode, (Master, Slave));
+
+pub struct I2c<S, R, M = Master> where S: Any + I2C, R: IsRemapped, M: I2cMode {
     i2c: S,
-    remapped: PhantomData<R>
+    remapped: PhantomData<R>,
+    mode: PhantomData<M>,
+    rcc: Option<RccPeripheral<S, rcc::Enabled>>,
+    config: I2cConfig,
+    timeout: Option<I2cTimeout>,
+    /// Set when a missing acknowledge ended a slave transmission, the stop signal
+    /// that may follow is not reported again
+    nacked: bool,
+}
+
+/// Register values written during initialization
+#[derive(Clone, Copy)]
+struct I2cConfig {
+    freq: u8,
+    trise: u8,
+    ccr: u16,
+    fast: bool,
+    duty: bool,
+    /// Core clock (HCLK) cycles per half period of the clock generated during bus recovery
+    recovery_delay: u32,
+    /// Own addresses, only used in slave mode
+    slave: Option<SlaveConfig>,
+    /// SMBus host mode
+    smbus: bool,
+    /// Packet error checking
+    pec: bool,
+    /// SMBus alert
+    alert: bool,
 }
 
+#[derive(Clone, Copy)]
+struct I2cTimeout {
+    timer: MonoTimer,
+    ticks: u32,
+}
+
+/// Pin numbers of SCL and SDA on GPIOB, used to recover the bus
+pub trait BusPins {
+    fn scl() -> u8;
+    fn sda() -> u8;
+}
+
+impl BusPins for I2cBusPorts<I2C1, NotRemapped> { fn scl() -> u8 { 6 } fn sda() -> u8 { 7 } }
+impl BusPins for I2cBusPorts<I2C1, Remapped> { fn scl() -> u8 { 8 } fn sda() -> u8 { 9 } }
+impl BusPins for I2cBusPorts<I2C2, NotRemapped> { fn scl() -> u8 { 10 } fn sda() -> u8 { 11 } }
+
 pub struct I2cBusPorts<S, R> where S: Any + I2C, R: IsRemapped {
     i2c: PhantomData<S>,
     remapped: PhantomData<R>
@@ -101,8 +255,123 @@ pub enum I2cDutyCycle {
     Ratio16to9,
 }
 
+/// Address of a slave, without the read / write bit
+#[derive(PartialEq, Clone, Copy)]
+pub enum Address {
+    Seven(u8),
+    Ten(u16),
+}
+
+impl From<u8> for Address {
+    fn from(addr: u8) -> Self {
+        Address::Seven(addr)
+    }
+}
+
+impl Address {
+    /// Returns the header byte of a 10-bit address, containing its two most 
+    /// significant bits, without the read / write bit
+    #[inline(always)]
+    fn header(addr: u16) -> u8 {
+        0b1111_0000 | ((addr >> 7) as u8 & 0b110)
+    }
+}
+
+/// Set of 7-bit addresses, returned by `I2c::scan`
+#[derive(PartialEq, Clone, Copy)]
+pub struct AddressSet {
+    bits: [u32; 4],
+}
+
+impl AddressSet {
+    fn new() -> Self {
+        AddressSet { bits: [0; 4] }
+    }
+
+    fn insert(&mut self, addr: u8) {
+        self.bits[addr as usize / 32] |= 1 << (addr % 32);
+    }
+
+    /// Returns `true` if the set contains the address
+    pub fn contains(&self, addr: u8) -> bool {
+        addr < 0x80 && self.bits[addr as usize / 32] & (1 << (addr % 32)) != 0
+    }
+
+    /// Returns the number of addresses in the set
+    pub fn len(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Returns `true` if the set is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-impl<S, R> I2c<S, R>
+    /// Iterates over the addresses in ascending order
+    pub fn iter(&self) -> AddressSetIter {
+        AddressSetIter { set: *self, next: 0 }
+    }
+}
+
+pub struct AddressSetIter {
+    set: AddressSet,
+    next: u8,
+}
+
+impl Iterator for AddressSetIter {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        while self.next < 0x80 {
+            let addr = self.next;
+            self.next += 1;
+            if self.set.contains(addr) {
+                return Some(addr);
+            }
+        }
+        None
+    }
+}
+
+/// Addresses a slave responds to
+#[derive(Clone, Copy)]
+pub struct SlaveConfig {
+    /// Primary 7-bit address
+    pub address: u8,
+    /// Secondary 7-bit address (dual addressing mode)
+    pub address2: Option<u8>,
+    /// Respond to the general call address (0x00)
+    pub general_call: bool,
+}
+
+/// Address of a slave matched by the master
+#[derive(PartialEq, Clone, Copy)]
+pub enum SlaveAddress {
+    Primary,
+    Secondary,
+    GeneralCall,
+}
+
+/// Bus event reported to a slave
+#[derive(PartialEq, Clone, Copy)]
+pub enum SlaveEvent {
+    /// The master addressed this slave
+    /// 
+    /// `read` is `true` if the master reads from this slave.
+    AddressMatch { address: SlaveAddress, read: bool },
+    /// The master wrote a byte
+    Received(u8),
+    /// The master reads the next byte, which has to be provided using `respond`
+    ByteRequested,
+    /// The master ended the transfer
+    /// 
+    /// Reported once per transaction: for a stop signal or, when the master reads,
+    /// for the missing acknowledge of the last byte.
+    Stop,
+}
+
+
+impl<S, R> I2c<S, R, Master>
 where
     S: Any + I2C,
     R: IsRemapped
@@ -112,9 +381,9 @@ where
     /// 
     /// # Arguments
     /// - `i2c`: The I2C module.
-    /// - `freq`: The I2c bus frequency, typically either 100 kHz (slow mode) or 400 kHz (fast mode)    
-    /// - `duty`: The duty cycle of the I2c module.
-    pub fn new<F>(i2c: S, ports: I2cBusPorts<S, R>, freq: F, clocks: Clocks, duty: I2cDutyCycle) -> Self 
+    /// - `freq`: The I2c bus frequency, typically either 100 kHz (standard mode) or 400 kHz (fast mode)    
+    /// - `duty`: The duty cycle of the I2c module, only used in fast mode.
+    pub fn new<F>(i2c: S, ports: I2cBusPorts<S, R>, rcc_periph: RccPeripheral<S, rcc::Enabled>, freq: F, clocks: Clocks, duty: I2cDutyCycle) -> Self 
         where F: Into<Hertz> {
 
         let freq = freq.into().0;
@@ -122,16 +391,22 @@ where
 
         let i2cclk = clocks.pclk1().0;
         let freqrange = i2cclk / 1000000;
+        assert!((2..=36).contains(&freqrange));
 
-        i2c.cr2.modify(|_, w| unsafe { w.freq().bits(freqrange as u8) });
-
-        let ratio = i2cclk / freq - 4;
-        if freq > 100_000 {
-            // slow mode
-            assert!(duty == I2cDutyCycle::Ratio1to1);
-            i2c.trise.write(|w| unsafe { w.trise().bits((freqrange + 1) as u8) });
-            let ccr = i2cclk / (freq * 2); 
-            i2c.ccr.modify(|_, w| unsafe { w.f_s().clear_bit().ccr().bits(ccr as u16) })
+        let config = if freq <= 100_000 {
+            // standard mode
+            I2cConfig {
+                freq: freqrange as u8,
+                trise: (freqrange + 1) as u8,
+                ccr: (i2cclk / (freq * 2)).max(4) as u16,
+                fast: false,
+                duty: false,
+                recovery_delay: clocks.hclk().0 / (2 * 100_000),
+                slave: None,
+                smbus: false,
+                pec: false,
+                alert: false,
+            }
         } else {
             // fast mode
             let ccr = match duty {
@@ -139,18 +414,407 @@ where
                 I2cDutyCycle::Ratio16to9 => (i2cclk / (freq * 25)).max(1),
             };
 
-            i2c.trise.write(|w| unsafe { w.trise().bits((freqrange * 300 / 1000 + 1) as u8) });
-            i2c.ccr.modify(|_, w| unsafe { w.f_s().set_bit()
-                .duty().bit(duty == I2cDutyCycle::Ratio16to9)
-                .ccr().bits(ccr as u16) });
+            I2cConfig {
+                freq: freqrange as u8,
+                trise: (freqrange * 300 / 1000 + 1) as u8,
+                ccr: ccr as u16,
+                fast: true,
+                duty: duty == I2cDutyCycle::Ratio16to9,
+                recovery_delay: clocks.hclk().0 / (2 * 100_000),
+                slave: None,
+                smbus: false,
+                pec: false,
+                alert: false,
+            }
+        };
+
+        let i2c = Self {
+            i2c,
+            remapped: ports.remapped,
+            mode: PhantomData,
+            rcc: Some(rcc_periph),
+            config,
+            timeout: None,
+            nacked: false,
+        };
+        i2c.init();
+
+        i2c
+    }
+
+    /// Writes all bytes to the slave with the given 7-bit or 10-bit address
+    /// 
+    /// Without any bytes only the address is sent, followed by the stop signal.
+    pub fn write_to<A>(&mut self, addr: A, bytes: &[u8]) -> Result<(), Error> 
+        where A: Into<Address> {
+        let res = self.try_write(addr.into(), bytes);
+        self.release(res)
+    }
+
+    /// Reads into the buffer from the slave with the given 7-bit or 10-bit address
+    /// 
+    /// At least one byte has to be read, `Err(Error::InvalidLength)` is returned
+    /// for an empty buffer without accessing the bus.
+    pub fn read_from<A>(&mut self, addr: A, buffer: &mut [u8]) -> Result<(), Error> 
+        where A: Into<Address> {
+        if buffer.is_empty() {
+            return Err(Error::InvalidLength);
+        }
+        let res = self.try_read(addr.into(), buffer);
+        self.release(res)
+    }
+
+    /// Writes all bytes and reads into the buffer afterwards, using a repeated start
+    /// signal
+    /// 
+    /// Returns `Err(Error::InvalidLength)` for an empty buffer, see `read_from`.
+    pub fn write_read_to<A>(&mut self, addr: A, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> 
+        where A: Into<Address> {
+        if buffer.is_empty() {
+            return Err(Error::InvalidLength);
+        }
+        let res = self.try_write_read(addr.into(), bytes, buffer);
+        self.release(res)
+    }
+
+    /// Checks whether a slave acknowledges the given 7-bit address
+    /// 
+    /// Only the address is sent for writing, followed by the stop signal. Returns
+    /// `Ok(false)` if the address is not acknowledged (`Error::AF`) and an error if
+    /// the bus is faulty.
+    pub fn probe(&mut self, addr: u8) -> Result<bool, Error> {
+        assert!(addr < 0x80);
+
+        match self.start_address(addr << 1) {
+            Ok(()) => {
+                // clear ADDR
+                let res = self.read_sr2()
+                    .and_then(|_| self.stop())
+                    .and_then(|_| self.wait(Self::stop_complete));
+                self.release(res).map(|_| true)
+            },
+            Err(Error::AF) => self.release(Err(Error::AF)).or(Ok(false)),
+            Err(err) => self.release(Err(err)),
         }
-        
-        i2c.cr1.modify(|_, w| w.pe().set_bit());
+    }
 
-        Self {
-            i2c: i2c,
-            remapped: ports.remapped
+    /// Probes all 7-bit addresses except the reserved ones (`0x00` to `0x07` and
+    /// `0x78` to `0x7F`) and returns the addresses which are acknowledged
+    pub fn scan(&mut self) -> Result<AddressSet, Error> {
+        let mut found = AddressSet::new();
+
+        for addr in 0x08..0x78 {
+            if self.probe(addr)? {
+                found.insert(addr);
+            }
         }
+
+        Ok(found)
+    }
+
+    fn try_write(&mut self, addr: Address, bytes: &[u8]) -> Result<(), Error> {
+        self.address(addr, false)?;
+        self.transmit(bytes, false)?;
+
+        self.stop()?;
+        self.wait(Self::stop_complete)
+    }
+
+    fn try_read(&mut self, addr: Address, buffer: &mut [u8]) -> Result<(), Error> {
+        self.address(addr, true)?;
+        self.receive(buffer, false)
+    }
+
+    fn try_write_read(&mut self, addr: Address, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.address(addr, false)?;
+        self.transmit(bytes, false)?;
+
+        self.restart_read(addr)?;
+        self.receive(buffer, false)
+    }
+
+    /// Leaves the bus usable after a failed transfer: clears the error flags and
+    /// generates the stop signal, unless the arbitration has been lost and the bus 
+    /// has been released by the hardware already.
+    pub(crate) fn release<T>(&mut self, res: Result<T, Error>) -> Result<T, Error> {
+        if let Err(err) = res {
+            self.i2c.sr1.modify(|_, w| w.timeout().clear_bit()
+                .af().clear_bit()
+                .arlo().clear_bit()
+                .ovr().clear_bit()
+                .berr().clear_bit());
+
+            match err {
+                Error::ARLO => {},
+                _ => {
+                    self.stop().ok();
+                    self.wait(Self::stop_complete).ok();
+                },
+            }
+            self.i2c.cr1.modify(|_, w| w.pos().clear_bit());
+        }
+
+        res
+    }
+
+    /// Generates a repeated start signal to read from the slave, which has been 
+    /// addressed for writing before. A 10-bit slave remains addressed and only the 
+    /// header is sent.
+    pub(crate) fn restart_read(&mut self, addr: Address) -> Result<(), Error> {
+        match addr {
+            Address::Seven(addr) => self.start_address((addr << 1) | 1),
+            Address::Ten(addr) => self.start_address(Address::header(addr) | 1),
+        }
+    }
+
+    /// Generates a start signal and addresses the slave. The `ADDR` flag is not
+    /// cleared, see `start_address`.
+    /// 
+    /// A 10-bit slave is addressed for writing by the header and the second address 
+    /// byte. For reading a repeated start signal followed by the header with the 
+    /// read bit set is required afterwards.
+    pub(crate) fn address(&mut self, addr: Address, read: bool) -> Result<(), Error> {
+        match addr {
+            Address::Seven(addr) => {
+                assert!(addr < 0x80);
+                self.start_address((addr << 1) | read as u8)
+            },
+            Address::Ten(addr) => {
+                assert!(addr < 0x400);
+                let header = Address::header(addr);
+
+                self.start()?;
+                self.wait(Self::start_complete)?;
+
+                unsafe { self.write_unchecked(header)? };
+                self.wait(Self::add10_complete)?;
+                unsafe { self.write_unchecked(addr as u8)? };
+                self.wait(Self::addr_complete)?;
+
+                if read {
+                    // clear ADDR
+                    self.read_sr2()?;
+                    self.start_address(header | 1)?;
+                }
+
+                Ok(())
+            },
+        }
+    }
+}
+
+impl<S, R> I2c<S, R, Slave>
+where
+    S: Any + I2C,
+    R: IsRemapped
+{
+    /// Initializes the given I2c module as a slave
+    /// 
+    /// The slave acknowledges its addresses and all bytes written by the master. 
+    /// SCL is held low (clock stretching) until an event has been handled.
+    pub fn new_slave(i2c: S, ports: I2cBusPorts<S, R>, rcc_periph: RccPeripheral<S, rcc::Enabled>, clocks: Clocks, slave: SlaveConfig) -> Self {
+        let i2cclk = clocks.pclk1().0;
+        let freqrange = i2cclk / 1000000;
+        assert!((2..=36).contains(&freqrange));
+        assert!(slave.address < 0x80);
+
+        // the clock is generated by the master, the timing is only used for bus recovery
+        let config = I2cConfig {
+            freq: freqrange as u8,
+            trise: (freqrange + 1) as u8,
+            ccr: (i2cclk / (100_000 * 2)).max(4) as u16,
+            fast: false,
+            duty: false,
+            recovery_delay: clocks.hclk().0 / (2 * 100_000),
+            slave: Some(slave),
+            smbus: false,
+            pec: false,
+            alert: false,
+        };
+
+        let i2c = Self {
+            i2c,
+            remapped: ports.remapped,
+            mode: PhantomData,
+            rcc: Some(rcc_periph),
+            config,
+            timeout: None,
+            nacked: false,
+        };
+        i2c.init();
+
+        i2c
+    }
+
+    /// Returns the next bus event. Returns `Err(nb::Error::WouldBlock)` if no
+    /// event is pending.
+    /// 
+    /// # Remarks
+    /// 
+    /// This should be called from the event and error interrupt handlers until it
+    /// returns `Err(nb::Error::WouldBlock)`. The bus is stretched until the event
+    /// has been handled, `SlaveEvent::ByteRequested` has to be answered using 
+    /// `respond` before calling this function again.
+    pub fn event(&mut self) -> nb::Result<SlaveEvent, Error> {
+        let sr1 = self.i2c.sr1.read();
+
+        if sr1.af().bit_is_set() {
+            // the master ends a read by not acknowledging the last byte, which
+            // already ends the transaction
+            self.i2c.sr1.modify(|_, w| w.af().clear_bit());
+            self.nacked = true;
+            return Ok(SlaveEvent::Stop);
+        } else if sr1.berr().bit_is_set() {
+            self.i2c.sr1.modify(|_, w| w.berr().clear_bit());
+            return Err(nb::Error::Other(Error::BERR));
+        } else if sr1.arlo().bit_is_set() {
+            self.i2c.sr1.modify(|_, w| w.arlo().clear_bit());
+            return Err(nb::Error::Other(Error::ARLO));
+        } else if sr1.ovr().bit_is_set() {
+            self.i2c.sr1.modify(|_, w| w.ovr().clear_bit());
+            return Err(nb::Error::Other(Error::OVR));
+        }
+
+        if sr1.addr().bit_is_set() {
+            // reading SR2 after SR1 clears ADDR
+            let sr2 = self.i2c.sr2.read();
+            self.nacked = false;
+            let address = if sr2.gencall().bit_is_set() {
+                SlaveAddress::GeneralCall
+            } else if sr2.dualf().bit_is_set() {
+                SlaveAddress::Secondary
+            } else {
+                SlaveAddress::Primary
+            };
+
+            Ok(SlaveEvent::AddressMatch { address, read: sr2.tra().bit_is_set() })
+        } else if sr1.rx_ne().bit_is_set() {
+            Ok(SlaveEvent::Received(self.i2c.dr.read().dr().bits()))
+        } else if sr1.tx_e().bit_is_set() && self.i2c.sr2.read().tra().bit_is_set() {
+            Ok(SlaveEvent::ByteRequested)
+        } else if sr1.stopf().bit_is_set() {
+            // reading SR1 followed by writing CR1 clears STOPF
+            self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+            if self.nacked {
+                self.nacked = false;
+                Err(nb::Error::WouldBlock)
+            } else {
+                Ok(SlaveEvent::Stop)
+            }
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Provides the byte requested by the master, see `SlaveEvent::ByteRequested`
+    pub fn respond(&mut self, byte: u8) {
+        self.i2c.dr.write(|w| unsafe { w.dr().bits(byte) });
+    }
+}
+
+impl<S, R, M> I2c<S, R, M>
+where
+    S: Any + I2C,
+    R: IsRemapped,
+    M: I2cMode
+{
+    /// Writes the timing configuration and enables the peripheral
+    fn init(&self) {
+        let config = self.config;
+
+        self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+        self.i2c.cr2.modify(|_, w| unsafe { w.freq().bits(config.freq) });
+        self.i2c.trise.write(|w| unsafe { w.trise().bits(config.trise) });
+        self.i2c.ccr.write(|w| unsafe { w.f_s().bit(config.fast)
+            .duty().bit(config.duty)
+            .ccr().bits(config.ccr) });
+        self.i2c.cr1.modify(|_, w| w.smbus().bit(config.smbus)
+            .smbtype().bit(config.smbus)
+            .enpec().bit(config.pec)
+            .alert().bit(config.alert));
+
+        if let Some(slave) = config.slave {
+            // bit 14 has to be kept at 1 by software
+            self.i2c.oar1.write(|w| unsafe { w.bits((1 << 14) | ((slave.address as u32) << 1)) });
+            self.i2c.oar2.write(|w| unsafe { match slave.address2 {
+                Some(address2) => w.bits(((address2 as u32 & 0x7F) << 1) | 1),
+                None => w.bits(0),
+            }});
+            self.i2c.cr1.modify(|_, w| w.engc().bit(slave.general_call).nostretch().clear_bit());
+        }
+
+        self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+        if config.slave.is_some() {
+            // ACK is cleared by the hardware while the peripheral is disabled
+            self.i2c.cr1.modify(|_, w| w.ack().set_bit());
+        }
+    }
+
+    /// Limits the time each step of a transfer may take
+    /// 
+    /// Without a timeout a slave holding SDA or SCL low blocks the bus operations
+    /// forever. Once a timeout occurs, `Error::TimedOut` is returned.
+    /// 
+    /// # Arguments
+    /// - `timer`: The timer used to measure the elapsed time.
+    /// - `us`: The timeout in microseconds, limited to the range of the cycle 
+    ///   counter (about 59 s at 72 MHz).
+    pub fn set_timeout(&mut self, timer: MonoTimer, us: u32) {
+        let ticks = timer.frequency().0 as u64 * us as u64 / 1_000_000;
+        self.timeout = Some(I2cTimeout {
+            timer,
+            ticks: ticks.min(u32::MAX as u64) as u32,
+        });
+    }
+
+    /// Disables the timeout, after which operations may block forever
+    pub fn clear_timeout(&mut self) {
+        self.timeout = None;
+    }
+
+    /// Enables or disables the SMBus host mode and packet error checking
+    pub(crate) fn set_smbus(&mut self, smbus: bool, pec: bool, alert: bool) {
+        self.config.smbus = smbus;
+        self.config.pec = pec;
+        self.config.alert = alert;
+        self.init();
+    }
+
+    /// Returns `true` if a device signaled an SMBus alert, clearing the flag
+    pub(crate) fn take_alert(&mut self) -> bool {
+        if self.i2c.sr1.read().smbalert().bit_is_set() {
+            self.i2c.sr1.modify(|_, w| w.smbalert().clear_bit());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Polls `f` until it completes or the timeout expires
+    pub(crate) fn wait<F>(&self, f: F) -> Result<(), Error>
+        where F: Fn(&Self) -> nb::Result<(), Error> {
+        let start = self.timeout.map(|t| (t.timer.now(), t.ticks));
+
+        loop {
+            match f(self) {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::Other(err)) => return Err(err),
+                Err(nb::Error::WouldBlock) => {},
+            }
+
+            if let Some((instant, ticks)) = start {
+                if instant.elapsed() > ticks {
+                    return Err(Error::TimedOut);
+                }
+            }
+        }
+    }
+
+    /// Returns `Ok(())` once no stop signal is pending. Returns `Err(nb::Error::WouldBlock)` 
+    /// otherwise.
+    pub(crate) fn stop_complete(&self) -> nb::Result<(), Error> {
+        if self.i2c.cr1.read().stop().bit_is_set() { Err(nb::Error::WouldBlock) } else { Ok(()) }
     }
 
     /// Enable all I2C module interrupts.
@@ -158,6 +822,12 @@ where
         self.i2c.cr2.modify(|_, w| {w.itevten().set_bit().iterren().set_bit()})
     }
 
+    /// Enable the buffer interrupts, which are raised once the transmit buffer is
+    /// empty or the receive buffer is not empty. Required by the slave mode.
+    pub fn listen_buffer(&mut self) {
+        self.i2c.cr2.modify(|_, w| w.itbufen().set_bit())
+    }
+
     fn read_sr1(&self) -> Result<i2c1::sr1::R, Error> {
         let sr1 = self.i2c.sr1.read();
 
@@ -176,7 +846,7 @@ where
         Ok(sr1)
     }
 
-    fn read_sr2(&self) -> Result<i2c1::sr2::R, Error> {
+    pub(crate) fn read_sr2(&self) -> Result<i2c1::sr2::R, Error> {
         let sr2 = self.i2c.sr2.read();
         Ok(sr2)
     }
@@ -223,6 +893,15 @@ where
         }
     }
 
+    /// Returns `Ok(())` once the header of a 10-bit address has been sent. Returns 
+    /// `Err(nb::Error::WouldBlock)` otherwise.
+    pub fn add10_complete(&self) -> nb::Result<(), Error> {
+        match self.read_sr1() {
+            Ok(sr1) => if sr1.add10().bit_is_set() { Ok(()) } else { Err(nb::Error::WouldBlock) },
+            Err(err) => Err(nb::Error::Other(err))
+        }
+    }
+
     /// Returns `Ok(())` if the transmit buffer is empty. Returns `Err(nb::Error::WouldBlock)`
     /// otherwise.
     /// 
@@ -237,6 +916,21 @@ where
         }
     }
 
+    /// Returns `Ok(())` once the last byte has been transferred completely, meaning 
+    /// both the data register and the shift register are full (reception) or empty
+    /// (transmission). Returns `Err(nb::Error::WouldBlock)` otherwise.
+    /// 
+    /// # Remarks
+    /// 
+    /// Use `block!(i2c.byte_transfer_finished()).unwrap()` to continue polling
+    /// until event happens.
+    pub fn byte_transfer_finished(&self) -> nb::Result<(), Error> {
+        match self.read_sr1() {
+            Ok(sr1) => if sr1.btf().bit_is_set() { Ok(()) } else { Err(nb::Error::WouldBlock) },
+            Err(err) => Err(nb::Error::Other(err))
+        }
+    }
+
     /// Returns `Ok(())` if the receive buffer is not empty. Returns `Err(nb::Error::WouldBlock)`
     /// otherwise.
     /// 
@@ -264,69 +958,798 @@ where
         let dr = self.i2c.dr.read().dr().bits();
         Ok(dr)
     }
-}
-
-impl<S, R> hal_i2c::Write for I2c<S, R> 
-    where S: I2C + Any, R: IsRemapped {
 
-    type Error = Error;
+    /// Generates a (repeated) start signal and sends the address byte. The `ADDR`
+    /// flag is not cleared, as the receiver has to configure `ACK` and `POS` before.
+    pub(crate) fn start_address(&mut self, addr_byte: u8) -> Result<(), Error> {
+        self.start()?;
+        self.wait(Self::start_complete)?;
 
-    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
-        assert!(bytes.len() > 0);
+        unsafe { self.write_unchecked(addr_byte)? };
+        self.wait(Self::addr_complete)?;
 
-        self.start()?;
-        block!(self.start_complete())?;
+        Ok(())
+    }
 
-        unsafe { self.write_unchecked(addr & 0b1111_1110)? };
-        block!(self.addr_complete())?;
+    /// Transmits all bytes once the address has been acknowledged and waits until 
+    /// the last byte has been transferred.
+    /// 
+    /// If `pec` is set, the calculated PEC is transmitted after the last byte.
+    pub(crate) fn transmit(&mut self, bytes: &[u8], pec: bool) -> Result<(), Error> {
+        // clear ADDR
         self.read_sr2()?;
-        
+
+        if bytes.is_empty() && !pec {
+            // only the address has been sent, BTF is never set
+            return Ok(());
+        }
+
         for b in bytes {
-            block!(self.transmit_empty())?;
+            self.wait(Self::transmit_empty)?;
             unsafe { self.write_unchecked(*b)? };
         }
-        block!(self.transmit_empty())?;
-
-        self.stop()?;
+        if pec {
+            // the last byte has been moved to the shift register
+            self.wait(Self::transmit_empty)?;
+            self.i2c.cr1.modify(|_, w| w.pec().set_bit());
+        }
+        self.wait(Self::byte_transfer_finished)?;
 
         Ok(())
     }
-}
 
-impl<S, R> hal_i2c::WriteRead for I2c<S,R>
-    where S: I2C + Any, R: IsRemapped {
+    /// Receives into the buffer once the address has been acknowledged and 
+    /// generates the stop signal.
+    /// 
+    /// Follows the master receiver procedures of the reference manual: the ACK of
+    /// the last byte has to be disabled and the stop signal has to be requested 
+    /// while the second to last byte is being received, which depends on the
+    /// number of bytes.
+    /// 
+    /// If `pec` is set, the last byte of the buffer is the PEC sent by the slave,
+    /// which is checked by the hardware.
+    pub(crate) fn receive(&mut self, buffer: &mut [u8], pec: bool) -> Result<(), Error> {
+        let n = buffer.len();
+        assert!(n > 0);
 
-    type Error = Error;
+        match n {
+            1 => {
+                self.i2c.cr1.modify(|_, w| w.ack().clear_bit().pos().clear_bit());
+                // ADDR has to be cleared and STOP set without interruption (errata 2.13.2)
+                interrupt::free(|_| -> Result<(), Error> {
+                    self.read_sr2()?;
+                    self.stop()?;
+                    self.request_pec(pec);
+                    Ok(())
+                })?;
 
-    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
-        self.start()?;
-        block!(self.start_complete())?;
+                self.wait(Self::receive_not_empty)?;
+                buffer[0] = unsafe { self.read_unchecked()? };
+            },
+            2 => {
+                // NACK the byte in the shift register once the first byte is received
+                self.i2c.cr1.modify(|_, w| w.ack().clear_bit().pos().set_bit());
+                self.read_sr2()?;
+                // with POS set, the byte following the one in the shift register is the PEC
+                self.request_pec(pec);
 
-        unsafe { self.write_unchecked(addr & 0b1111_1110)? };
-        block!(self.addr_complete())?;
-        self.read_sr2()?;
+                // data 1 in DR, data 2 in the shift register
+                self.wait(Self::byte_transfer_finished)?;
+                interrupt::free(|_| -> Result<(), Error> {
+                    self.stop()?;
+                    buffer[0] = unsafe { self.read_unchecked()? };
+                    Ok(())
+                })?;
+                buffer[1] = unsafe { self.read_unchecked()? };
+            },
+            _ => {
+                self.i2c.cr1.modify(|_, w| w.ack().set_bit().pos().clear_bit());
+                self.read_sr2()?;
 
-        for b in bytes {
-            block!(self.transmit_empty())?;
-            unsafe { self.write_unchecked(*b)? };
+                self.receive_acknowledged(buffer, pec)?;
+            },
         }
-        block!(self.transmit_empty())?;
 
-        self.start()?;
-        block!(self.start_complete())?;
-        self.read_sr2()?;
-        
-        for b in buffer {
-            block!(self.receive_not_empty())?;
+        self.finish_receive(pec)
+    }
+
+    /// Receives the remaining bytes (at least three) of a reception in progress, 
+    /// with `ADDR` cleared and `ACK` set.
+    fn receive_acknowledged(&mut self, buffer: &mut [u8], pec: bool) -> Result<(), Error> {
+        let n = buffer.len();
+        let (head, tail) = buffer.split_at_mut(n - 3);
+        for b in head {
+            self.wait(Self::receive_not_empty)?;
             *b = unsafe { self.read_unchecked()? };
         }
 
-        self.stop()?;
+        // data N-2 in DR, data N-1 in the shift register
+        self.wait(Self::byte_transfer_finished)?;
+        self.i2c.cr1.modify(|_, w| w.ack().clear_bit());
+        tail[0] = unsafe { self.read_unchecked()? };
+        // data N is being received
+        self.request_pec(pec);
+
+        // data N-1 in DR, data N in the shift register
+        self.wait(Self::byte_transfer_finished)?;
+        interrupt::free(|_| -> Result<(), Error> {
+            self.stop()?;
+            tail[1] = unsafe { self.read_unchecked()? };
+            Ok(())
+        })?;
+
+        self.wait(Self::receive_not_empty)?;
+        tail[2] = unsafe { self.read_unchecked()? };
+
+        Ok(())
+    }
+
+    /// Receives an SMBus block once the address has been acknowledged: the first 
+    /// byte sent by the slave is the number of bytes following. Returns the number
+    /// of bytes written to the buffer.
+    /// 
+    /// # Remarks
+    /// 
+    /// The end of the transfer depends on the byte count, which is only known once
+    /// the second byte is already being received. The count is therefore read and 
+    /// the acknowledge programmed with interrupts disabled.
+    pub(crate) fn receive_block(&mut self, buffer: &mut [u8], pec: bool) -> Result<usize, Error> {
+        let mut block = [0u8; 34];
+
+        self.i2c.cr1.modify(|_, w| w.ack().set_bit().pos().clear_bit());
+        self.read_sr2()?;
+
+        self.wait(Self::receive_not_empty)?;
+        let count = interrupt::free(|_| -> Result<usize, Error> {
+            let count = unsafe { self.read_unchecked()? } as usize;
+
+            if count == 0 || count > buffer.len() || count + pec as usize == 1 {
+                // NACK the byte being received
+                self.i2c.cr1.modify(|_, w| w.ack().clear_bit());
+                self.stop()?;
+            }
+            Ok(count)
+        })?;
+
+        if count == 0 || count > buffer.len() {
+            self.wait(Self::receive_not_empty)?;
+            unsafe { self.read_unchecked()? };
+            self.finish_receive(false)?;
+            return Err(Error::InvalidLength);
+        }
+
+        let n = count + pec as usize;
+        match n {
+            1 => {
+                self.wait(Self::receive_not_empty)?;
+                block[0] = unsafe { self.read_unchecked()? };
+            },
+            2 => {
+                // data 1 in DR, data 2 in the shift register
+                self.wait(Self::receive_not_empty)?;
+                interrupt::free(|_| -> Result<(), Error> {
+                    self.i2c.cr1.modify(|_, w| w.ack().clear_bit());
+                    self.stop()?;
+                    block[0] = unsafe { self.read_unchecked()? };
+                    self.request_pec(pec);
+                    Ok(())
+                })?;
+
+                self.wait(Self::receive_not_empty)?;
+                block[1] = unsafe { self.read_unchecked()? };
+            },
+            _ => self.receive_acknowledged(&mut block[..n], pec)?,
+        }
+        self.finish_receive(pec)?;
+
+        buffer[..count].copy_from_slice(&block[..count]);
+        Ok(count)
+    }
+
+    /// Marks the byte being received as PEC
+    #[inline(always)]
+    fn request_pec(&mut self, pec: bool) {
+        if pec {
+            self.i2c.cr1.modify(|_, w| w.pec().set_bit());
+        }
+    }
+
+    /// Waits for the stop signal and checks the received PEC
+    fn finish_receive(&mut self, pec: bool) -> Result<(), Error> {
+        self.wait(Self::stop_complete)?;
+        self.i2c.cr1.modify(|_, w| w.pos().clear_bit());
+
+        if pec && self.i2c.sr1.read().pecerr().bit_is_set() {
+            self.i2c.sr1.modify(|_, w| w.pecerr().clear_bit());
+            return Err(Error::PEC);
+        }
 
         Ok(())
     }
 }
 
+impl<S, R, M> I2c<S, R, M>
+where
+    S: Any + I2C,
+    R: IsRemapped,
+    M: I2cMode,
+    I2cBusPorts<S, R>: BusPins
+{
+    /// Frees a bus which is blocked by a slave holding SDA low
+    /// 
+    /// SCL and SDA are temporarily driven as GPIO pins: up to nine clock pulses
+    /// are generated until the slave releases SDA, followed by a stop signal. 
+    /// Afterwards the peripheral is reset and initialized again, interrupts enabled
+    /// using `listen` have to be enabled again.
+    /// 
+    /// Returns `Err(Error::BERR)` if SDA is still held low.
+    pub fn recover_bus(&mut self) -> Result<(), Error> {
+        let scl = I2cBusPorts::<S, R>::scl();
+        let sda = I2cBusPorts::<S, R>::sda();
+        let delay = self.config.recovery_delay.max(1);
+
+        self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+
+        let gpio = unsafe { &*GPIOB::ptr() };
+
+        // replaces the configuration nibble of a pin, returning the previous one
+        let configure = |pin: u8, cnf: u32| -> u32 {
+            let shift = 4 * (pin as u32 % 8);
+            let old = if pin < 8 { gpio.crl.read().bits() } else { gpio.crh.read().bits() };
+            let new = (old & !(0b1111 << shift)) | (cnf << shift);
+            if pin < 8 {
+                gpio.crl.write(|w| unsafe { w.bits(new) });
+            } else {
+                gpio.crh.write(|w| unsafe { w.bits(new) });
+            }
+            (old >> shift) & 0b1111
+        };
+
+        let set = |pin: u8, high: bool| unsafe {
+            gpio.bsrr.write(|w| w.bits(1 << (pin + if high { 0 } else { 16 })))
+        };
+        let read = |pin: u8| gpio.idr.read().bits() & (1 << pin) != 0;
+
+        // release both lines and switch to general purpose open drain outputs
+        set(scl, true);
+        set(sda, true);
+        let scl_cnf = configure(scl, 0b0111);
+        let sda_cnf = configure(sda, 0b0111);
+        asm::delay(delay);
+
+        // clock out the byte the slave is trying to send
+        for _ in 0..9 {
+            if read(sda) {
+                break;
+            }
+
+            set(scl, false);
+            asm::delay(delay);
+            set(scl, true);
+            asm::delay(delay);
+        }
+
+        // stop signal: SDA rising while SCL is high
+        set(scl, false);
+        asm::delay(delay);
+        set(sda, false);
+        asm::delay(delay);
+        set(scl, true);
+        asm::delay(delay);
+        set(sda, true);
+        asm::delay(delay);
+
+        let released = read(sda) && read(scl);
+
+        // hand the pins back to the peripheral
+        configure(scl, scl_cnf);
+        configure(sda, sda_cnf);
+
+        self.rcc = self.rcc.take().map(S::reset);
+        self.init();
+
+        if released { Ok(()) } else { Err(Error::BERR) }
+    }
+}
+
+/// State of an interrupt driven transaction
+#[derive(PartialEq, Clone, Copy)]
+enum TransactionState {
+    /// Waiting for the start signal
+    Start,
+    /// The header of a 10-bit address has been sent
+    Header,
+    /// The address has been sent for writing
+    WriteAddress,
+    Transmit,
+    /// Waiting for the repeated start signal
+    Restart,
+    /// The address has been sent for reading
+    ReadAddress,
+    Receive,
+    Done,
+}
+
+/// Transaction progressed by the I2C interrupts
+pub struct I2cTransaction<S, R> where S: Any + I2C, R: IsRemapped {
+    i2c: I2c<S, R, Master>,
+    addr: Address,
+    bytes: &'static [u8],
+    buffer: &'static mut [u8],
+    state: TransactionState,
+    written: usize,
+    received: usize,
+    result: Option<Result<(), Error>>,
+}
+
+impl<S, R> I2c<S, R, Master>
+where
+    S: Any + I2C,
+    R: IsRemapped
+{
+    /// Starts writing all bytes to the slave in the background
+    pub fn write_async<A>(self, addr: A, bytes: &'static [u8]) -> I2cTransaction<S, R> 
+        where A: Into<Address> {
+        self.transaction(addr.into(), bytes, &mut [])
+    }
+
+    /// Starts reading into the buffer from the slave in the background
+    pub fn read_async<A>(self, addr: A, buffer: &'static mut [u8]) -> I2cTransaction<S, R> 
+        where A: Into<Address> {
+        self.transaction(addr.into(), &[], buffer)
+    }
+
+    /// Starts writing all bytes and reading into the buffer afterwards in the 
+    /// background, using a repeated start signal
+    pub fn write_read_async<A>(self, addr: A, bytes: &'static [u8], buffer: &'static mut [u8]) -> I2cTransaction<S, R> 
+        where A: Into<Address> {
+        self.transaction(addr.into(), bytes, buffer)
+    }
+
+    /// Enables the interrupts and generates the start signal
+    fn transaction(mut self, addr: Address, bytes: &'static [u8], buffer: &'static mut [u8]) -> I2cTransaction<S, R> {
+        assert!(!bytes.is_empty() || !buffer.is_empty());
+        match addr {
+            Address::Seven(addr) => assert!(addr < 0x80),
+            Address::Ten(addr) => assert!(addr < 0x400),
+        }
+
+        self.i2c.cr1.modify(|_, w| w.ack().clear_bit().pos().clear_bit());
+        self.i2c.cr2.modify(|_, w| w.itevten().set_bit().itbufen().set_bit().iterren().set_bit());
+        self.start().ok();
+
+        I2cTransaction {
+            i2c: self,
+            addr,
+            bytes,
+            buffer,
+            state: TransactionState::Start,
+            written: 0,
+            received: 0,
+            result: None,
+        }
+    }
+}
+
+impl<S, R> I2cTransaction<S, R>
+where
+    S: Any + I2C,
+    R: IsRemapped
+{
+    /// Progresses the transaction, has to be called from the I2C event interrupt
+    /// handler. Returns `true` once the transaction has completed.
+    pub fn on_event(&mut self) -> bool {
+        if let Some(result) = self.progress() {
+            self.complete(result);
+        }
+
+        self.state == TransactionState::Done
+    }
+
+    /// Handles the pending event, returns the result once the last step has been
+    /// performed
+    fn progress(&mut self) -> Option<Result<(), Error>> {
+        let regs = &self.i2c.i2c;
+        let sr1 = regs.sr1.read();
+
+        match self.state {
+            TransactionState::Start if sr1.sb().bit_is_set() => {
+                let read = self.bytes.is_empty();
+                match self.addr {
+                    Address::Seven(addr) => {
+                        regs.dr.write(|w| unsafe { w.dr().bits((addr << 1) | read as u8) });
+                        self.state = if read { TransactionState::ReadAddress } else { TransactionState::WriteAddress };
+                    },
+                    Address::Ten(addr) => {
+                        // a 10-bit slave is always addressed for writing first
+                        regs.dr.write(|w| unsafe { w.dr().bits(Address::header(addr)) });
+                        self.state = TransactionState::Header;
+                    },
+                }
+            },
+            TransactionState::Header if sr1.add10().bit_is_set() => {
+                if let Address::Ten(addr) = self.addr {
+                    regs.dr.write(|w| unsafe { w.dr().bits(addr as u8) });
+                }
+                self.state = TransactionState::WriteAddress;
+            },
+            TransactionState::WriteAddress if sr1.addr().bit_is_set() => {
+                // clear ADDR
+                regs.sr2.read();
+                if !self.bytes.is_empty() {
+                    self.state = TransactionState::Transmit;
+                } else {
+                    regs.cr1.modify(|_, w| w.start().set_bit());
+                    self.state = TransactionState::Restart;
+                }
+            },
+            TransactionState::Transmit => {
+                if self.written < self.bytes.len() {
+                    if sr1.tx_e().bit_is_set() {
+                        regs.dr.write(|w| unsafe { w.dr().bits(self.bytes[self.written]) });
+                        self.written += 1;
+                        if self.written == self.bytes.len() {
+                            // wait for BTF, TXE would interrupt continuously
+                            regs.cr2.modify(|_, w| w.itbufen().clear_bit());
+                        }
+                    }
+                } else if sr1.btf().bit_is_set() {
+                    if !self.buffer.is_empty() {
+                        regs.cr2.modify(|_, w| w.itbufen().set_bit());
+                        regs.cr1.modify(|_, w| w.start().set_bit());
+                        self.state = TransactionState::Restart;
+                    } else {
+                        regs.cr1.modify(|_, w| w.stop().set_bit());
+                        return Some(Ok(()));
+                    }
+                }
+            },
+            TransactionState::Restart if sr1.sb().bit_is_set() => {
+                let addr_byte = match self.addr {
+                    Address::Seven(addr) => (addr << 1) | 1,
+                    Address::Ten(addr) => Address::header(addr) | 1,
+                };
+                regs.dr.write(|w| unsafe { w.dr().bits(addr_byte) });
+                self.state = TransactionState::ReadAddress;
+            },
+            TransactionState::ReadAddress if sr1.addr().bit_is_set() => {
+                // see `I2c::receive` for the procedures depending on the number of bytes
+                match self.buffer.len() {
+                    1 => {
+                        regs.cr1.modify(|_, w| w.ack().clear_bit());
+                        regs.sr2.read();
+                        regs.cr1.modify(|_, w| w.stop().set_bit());
+                    },
+                    2 => {
+                        regs.cr1.modify(|_, w| w.ack().clear_bit().pos().set_bit());
+                        regs.sr2.read();
+                        regs.cr2.modify(|_, w| w.itbufen().clear_bit());
+                    },
+                    n => {
+                        regs.cr1.modify(|_, w| w.ack().set_bit());
+                        regs.sr2.read();
+                        if n == 3 {
+                            regs.cr2.modify(|_, w| w.itbufen().clear_bit());
+                        }
+                    },
+                }
+                self.state = TransactionState::Receive;
+            },
+            TransactionState::Receive => {
+                let n = self.buffer.len();
+                let remaining = n - self.received;
+
+                if n == 2 {
+                    if sr1.btf().bit_is_set() {
+                        regs.cr1.modify(|_, w| w.stop().set_bit());
+                        self.buffer[0] = regs.dr.read().dr().bits();
+                        self.buffer[1] = regs.dr.read().dr().bits();
+                        return Some(Ok(()));
+                    }
+                } else if remaining > 3 || remaining == 1 {
+                    if sr1.rx_ne().bit_is_set() {
+                        self.buffer[self.received] = regs.dr.read().dr().bits();
+                        self.received += 1;
+                        if remaining == 4 {
+                            // the last three bytes are handled using BTF
+                            regs.cr2.modify(|_, w| w.itbufen().clear_bit());
+                        } else if remaining == 1 {
+                            return Some(Ok(()));
+                        }
+                    }
+                } else if sr1.btf().bit_is_set() {
+                    if remaining == 3 {
+                        // data N-2 in DR, data N-1 in the shift register
+                        regs.cr1.modify(|_, w| w.ack().clear_bit());
+                    } else {
+                        // data N-1 in DR, data N in the shift register
+                        regs.cr1.modify(|_, w| w.stop().set_bit());
+                        regs.cr2.modify(|_, w| w.itbufen().set_bit());
+                    }
+                    self.buffer[self.received] = regs.dr.read().dr().bits();
+                    self.received += 1;
+                }
+            },
+            _ => {},
+        }
+
+        None
+    }
+
+    /// Handles bus errors, has to be called from the I2C error interrupt handler.
+    /// Returns `true` once the transaction has completed.
+    pub fn on_error(&mut self) -> bool {
+        if self.state == TransactionState::Done {
+            return true;
+        }
+
+        if let Err(err) = self.i2c.read_sr1() {
+            self.i2c.i2c.sr1.modify(|_, w| w.timeout().clear_bit()
+                .af().clear_bit()
+                .arlo().clear_bit()
+                .ovr().clear_bit()
+                .berr().clear_bit());
+
+            // the bus is released by the hardware once the arbitration is lost
+            match err {
+                Error::ARLO => {},
+                _ => self.i2c.i2c.cr1.modify(|_, w| w.stop().set_bit()),
+            }
+            self.complete(Err(err));
+        }
+
+        self.state == TransactionState::Done
+    }
+
+    fn complete(&mut self, result: Result<(), Error>) {
+        self.i2c.i2c.cr2.modify(|_, w| w.itevten().clear_bit().itbufen().clear_bit().iterren().clear_bit());
+        self.state = TransactionState::Done;
+        self.result = Some(result);
+    }
+
+    /// Returns the result once the transaction has completed and the stop signal
+    /// has been generated. Returns `Err(nb::Error::WouldBlock)` otherwise.
+    pub fn poll(&self) -> nb::Result<(), Error> {
+        match self.result {
+            Some(result) => {
+                self.i2c.stop_complete()?;
+                self.i2c.i2c.cr1.modify(|_, w| w.pos().clear_bit());
+                result.map_err(nb::Error::Other)
+            },
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    /// Returns the buffers and the I2C module. A transaction which has not completed
+    /// yet is aborted by generating a stop signal.
+    pub fn free(mut self) -> (&'static [u8], &'static mut [u8], I2c<S, R, Master>) {
+        if self.state != TransactionState::Done {
+            self.i2c.i2c.cr1.modify(|_, w| w.stop().set_bit());
+            self.complete(Err(Error::Aborted));
+        }
+        self.i2c.wait(I2c::stop_complete).ok();
+        self.i2c.i2c.cr1.modify(|_, w| w.pos().clear_bit());
+
+        (self.bytes, self.buffer, self.i2c)
+    }
+}
+
+/// I2C master with the DMA channels used for reception and transmission
+pub struct I2cDma<S, R> where S: Any + I2C, R: IsRemapped {
+    i2c: I2c<S, R, Master>,
+    tx: DmaChannel<S::DmaTx, DMA1>,
+    rx: DmaChannel<S::DmaRx, DMA1>,
+}
+
+/// An ongoing DMA transfer, which owns the buffer `B` until it has completed
+pub struct I2cDmaTransfer<S, R, B> where S: Any + I2C, R: IsRemapped {
+    dma: I2cDma<S, R>,
+    buffer: B,
+    receive: bool,
+    /// The stop signal has been requested already
+    stopped: bool,
+    /// Error of the address phase
+    error: Option<Error>,
+}
+
+impl<S, R> I2c<S, R, Master>
+where
+    S: Any + I2C,
+    R: IsRemapped
+{
+    /// Uses the given DMA channels for transfers
+    pub fn with_dma(self, tx: DmaChannel<S::DmaTx, DMA1>, rx: DmaChannel<S::DmaRx, DMA1>) -> I2cDma<S, R> {
+        I2cDma {
+            i2c: self,
+            tx,
+            rx,
+        }
+    }
+}
+
+impl<S, R> I2cDma<S, R>
+where
+    S: Any + I2C,
+    R: IsRemapped
+{
+    /// Releases the `I2c` and the DMA channels
+    pub fn free(self) -> (I2c<S, R, Master>, DmaChannel<S::DmaTx, DMA1>, DmaChannel<S::DmaRx, DMA1>) {
+        (self.i2c, self.tx, self.rx)
+    }
+
+    #[inline(always)]
+    fn dr(&self) -> u32 {
+        &self.i2c.i2c.dr as *const _ as u32
+    }
+
+    /// Addresses the slave and transmits the buffer
+    /// 
+    /// The address is sent by the processor, blocking until it has been acknowledged.
+    /// If addressing fails, the error is returned by `wait`.
+    pub fn write<A>(mut self, addr: A, buffer: &'static [u8]) -> I2cDmaTransfer<S, R, &'static [u8]> 
+        where A: Into<Address> {
+        assert!(!buffer.is_empty());
+
+        if let Err(err) = self.i2c.address(addr.into(), false) {
+            return I2cDmaTransfer { dma: self, buffer, receive: false, stopped: false, error: Some(err) };
+        }
+
+        let dr = self.dr();
+        atomic::compiler_fence(Ordering::Release);
+
+        self.tx.start(dr, buffer.as_ptr() as u32, buffer.len(), dma::Direction::MemoryToPeripheral, true);
+        self.i2c.i2c.cr2.modify(|_, w| w.dmaen().set_bit().last().clear_bit());
+        // clear ADDR
+        self.i2c.i2c.sr2.read();
+
+        I2cDmaTransfer {
+            dma: self,
+            buffer,
+            receive: false,
+            stopped: false,
+            error: None,
+        }
+    }
+
+    /// Addresses the slave and fills the buffer with the received bytes
+    /// 
+    /// The last byte is not acknowledged, as `LAST` is set for the DMA transfer.
+    /// If addressing fails, the error is returned by `wait`.
+    pub fn read<A>(mut self, addr: A, buffer: &'static mut [u8]) -> I2cDmaTransfer<S, R, &'static mut [u8]> 
+        where A: Into<Address> {
+        assert!(!buffer.is_empty());
+
+        if let Err(err) = self.i2c.address(addr.into(), true) {
+            return I2cDmaTransfer { dma: self, buffer, receive: true, stopped: false, error: Some(err) };
+        }
+
+        let dr = self.dr();
+        let single = buffer.len() == 1;
+        atomic::compiler_fence(Ordering::Release);
+
+        self.rx.start(dr, buffer.as_mut_ptr() as u32, buffer.len(), dma::Direction::PeripheralToMemory, true);
+        self.i2c.i2c.cr2.modify(|_, w| w.dmaen().set_bit().last().set_bit());
+
+        if single {
+            // a single byte has to be NACKed before ADDR is cleared
+            self.i2c.i2c.cr1.modify(|_, w| w.ack().clear_bit().pos().clear_bit());
+            interrupt::free(|_| {
+                self.i2c.i2c.sr2.read();
+                self.i2c.i2c.cr1.modify(|_, w| w.stop().set_bit());
+            });
+        } else {
+            self.i2c.i2c.cr1.modify(|_, w| w.ack().set_bit().pos().clear_bit());
+            self.i2c.i2c.sr2.read();
+        }
+
+        I2cDmaTransfer {
+            dma: self,
+            buffer,
+            receive: true,
+            stopped: single,
+            error: None,
+        }
+    }
+}
+
+impl<S, R, B> I2cDmaTransfer<S, R, B>
+where
+    S: Any + I2C,
+    R: IsRemapped
+{
+    fn check(&self) -> nb::Result<(), Error> {
+        if let Some(err) = self.error {
+            return Err(nb::Error::Other(err));
+        }
+
+        let dma = &self.dma;
+        let sr1 = dma.i2c.read_sr1().map_err(nb::Error::Other)?;
+
+        let done = if self.receive {
+            dma.rx.is_complete()
+        } else {
+            dma.tx.is_complete() && sr1.btf().bit_is_set()
+        };
+
+        if dma.tx.has_error() || dma.rx.has_error() {
+            Err(nb::Error::Other(Error::TransferError))
+        } else if done {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Returns `true` once the transfer has finished, either successfully or with an error
+    pub fn is_done(&self) -> bool {
+        !matches!(self.check(), Err(nb::Error::WouldBlock))
+    }
+
+    /// Waits for the transfer to finish, generates the stop signal and returns the 
+    /// buffer and the bus
+    /// 
+    /// The result indicates whether the transfer was successful.
+    pub fn wait(mut self) -> (Result<(), Error>, B, I2cDma<S, R>) {
+        let mut res = self.dma.i2c.wait(|_| self.check());
+
+        {
+            let i2c = &mut self.dma.i2c;
+            match res {
+                // the bus is released by the hardware once the arbitration is lost
+                Err(Error::ARLO) => {},
+                _ => if !self.stopped { i2c.stop().ok(); },
+            }
+
+            i2c.i2c.sr1.modify(|_, w| w.timeout().clear_bit()
+                .af().clear_bit()
+                .arlo().clear_bit()
+                .ovr().clear_bit()
+                .berr().clear_bit());
+
+            if let Err(err) = i2c.wait(I2c::stop_complete) {
+                res = res.and(Err(err));
+            }
+            i2c.i2c.cr2.modify(|_, w| w.dmaen().clear_bit().last().clear_bit());
+        }
+        self.dma.tx.stop();
+        self.dma.rx.stop();
+
+        atomic::compiler_fence(Ordering::Acquire);
+
+        (res, self.buffer, self.dma)
+    }
+}
+
+impl<S, R> hal_i2c::Write for I2c<S, R> 
+    where S: I2C + Any, R: IsRemapped {
+
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.write_to(Address::Seven(addr), bytes)
+    }
+}
+
+impl<S, R> hal_i2c::Read for I2c<S, R> 
+    where S: I2C + Any, R: IsRemapped {
+
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.read_from(Address::Seven(addr), buffer)
+    }
+}
+
+impl<S, R> hal_i2c::WriteRead for I2c<S,R>
+    where S: I2C + Any, R: IsRemapped {
+
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.write_read_to(Address::Seven(addr), bytes, buffer)
+    }
+}
+
 impl I2c<I2C1, NotRemapped> {
     #[inline(always)]
     pub fn ports_normal<M>( 
@@ -341,6 +1764,19 @@ impl I2c<I2C1, NotRemapped> {
         }
 }
 
+impl I2c<I2C2, NotRemapped> {
+    #[inline(always)]
+    pub fn ports_i2c2<M>( 
+        _pb10 : GpioPin<GPIOB, Pin10, M, PinCnf3>, 
+        _pb11 : GpioPin<GPIOB, Pin11, M, PinCnf3>) 
+        -> I2cBusPorts<I2C2, NotRemapped> where M : PinOutput + PinMode {
+            I2cBusPorts {
+                i2c: PhantomData,
+                remapped: PhantomData
+            }
+        }
+}
+
 impl I2c<I2C1, Remapped> {
     #[inline(always)]
     pub fn ports_remapped<M>( 
@@ -0,0 +1,466 @@
+//! Inter-IC Sound
+//! 
+//! On high-density devices SPI2 and SPI3 can be used as I2S interfaces. Only the
+//! master modes are supported, the clock of the I2S interface is the system clock.
+//! 
+//! # Example
+//! 
+//! ```
+//! let pb12 = pinsb.12.set_output_50MHz().set_alt_output_push_pull();
+//! let pb13 = pinsb.13.set_output_50MHz().set_alt_output_push_pull();
+//! let pb15 = pinsb.15.set_output_50MHz().set_alt_output_push_pull();
+//! let pc6 = pinsc.6.set_output_50MHz().set_alt_output_push_pull();
+//! 
+//! let i2s2 = I2s::new(
+//!     &dp.SPI2,
+//!     I2s::ports_i2s2_transmit(pb12, pb13, pb15).master_clock(pc6),
+//!     rcc.peripherals.spi2.enable(),
+//!     Standard::Philips,
+//!     DataFormat::Data16Channel16,
+//!     48_000.hz(),
+//!     clocks,
+//! ).unwrap();
+//! 
+//! static mut BUFFER: [u16; 512] = [0; 512];
+//! let mut stream = i2s2.stream_transmit(dma1_channels.c5, unsafe { &mut BUFFER });
+//! 
+//! loop {
+//!     if let Ok(half) = stream.poll() {
+//!         fill_samples(stream.half_mut(half));
+//!     }
+//! }
+//! ```
+//! 
+//! # Pins
+//! 
+//! | Instance | WS   | CK   | SD   | MCK |
+//! |----------|------|------|------|-----|
+//! | I2S2     | PB12 | PB13 | PB15 | PC6 |
+//! | I2S3     | PA15 | PB3  | PB5  | PC7 |
+//! 
+//! The I2S3 pins are shared with JTAG, which has to be released using
+//! `afio_periph.swj.release_jtag()` first.
+
+#[allow(unused_imports)]
+use common;
+
+use core::any::Any;
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{self, Ordering};
+
+use nb;
+use rcc;
+
+use afio::{Swj, JtagReleased};
+use dma::{self, DmaChannel};
+use gpio::{Input, GpioPin, Pin3, Pin5, Pin6, Pin7, Pin12, Pin13, Pin15, PinMode, PinOutput, PinCnf1, PinCnf2};
+use rcc::{Clocks, RccPeripheral};
+use spi::SPI;
+use time::Hertz;
+use stm32f103xx::{GPIOA, GPIOB, GPIOC, SPI2, SPI3};
+
+type_states!(I2sMode, (MasterTransmit, MasterReceive));
+
+/// I2S error
+#[derive(Debug, Copy, Clone)]
+pub enum Error {
+    /// Overrun
+    /// 
+    /// A sample has been received before the previous sample has been read.
+    Overrun,
+    /// DMA Transfer Error
+    /// 
+    /// The DMA controller could not access the buffer.
+    TransferError,
+    /// Impossible Sample Rate
+    /// 
+    /// The sample rate cannot be derived from the system clock.
+    SampleRate,
+}
+
+/// Audio protocol
+#[derive(PartialEq, Clone, Copy)]
+pub enum Standard {
+    /// I2S Philips standard
+    Philips,
+    /// Left justified
+    Msb,
+    /// Right justified
+    Lsb,
+    /// PCM with short frame synchronisation
+    PcmShort,
+    /// PCM with long frame synchronisation
+    PcmLong,
+}
+
+/// Length of the data and of the channel frame the data is transmitted in
+/// 
+/// 24 and 32 bit data is transferred as two 16 bit half words, the most significant
+/// half word first.
+#[derive(PartialEq, Clone, Copy)]
+pub enum DataFormat {
+    /// 16 bit data in a 16 bit channel frame
+    Data16Channel16,
+    /// 16 bit data in a 32 bit channel frame
+    Data16Channel32,
+    /// 24 bit data in a 32 bit channel frame
+    Data24Channel32,
+    /// 32 bit data in a 32 bit channel frame
+    Data32Channel32,
+}
+
+/// Audio channel of a sample
+#[derive(PartialEq, Clone, Copy)]
+pub enum Channel {
+    Left,
+    Right,
+}
+
+/// Half of a DMA stream buffer
+#[derive(PartialEq, Clone, Copy)]
+pub enum Half {
+    First,
+    Second,
+}
+
+pub struct I2sPorts<S, M>
+where S: Any + SPI, M: I2sMode {
+    i2s: PhantomData<(S, M)>,
+    mck: bool,
+}
+
+impl<M> I2sPorts<SPI2, M>
+where M: I2sMode {
+    /// Outputs the master clock (256 times the sample rate) on PC6
+    pub fn master_clock<O>(self, _pc6: GpioPin<GPIOC, Pin6, O, PinCnf2>) -> Self
+        where O: PinOutput + PinMode {
+        I2sPorts { i2s: PhantomData, mck: true }
+    }
+}
+
+impl<M> I2sPorts<SPI3, M>
+where M: I2sMode {
+    /// Outputs the master clock (256 times the sample rate) on PC7
+    pub fn master_clock<O>(self, _pc7: GpioPin<GPIOC, Pin7, O, PinCnf2>) -> Self
+        where O: PinOutput + PinMode {
+        I2sPorts { i2s: PhantomData, mck: true }
+    }
+}
+
+pub struct I2s<'a, S, M>
+where S: Any + SPI, M: I2sMode {
+    i2s: &'a S,
+    mode: PhantomData<M>,
+    format: DataFormat,
+    sample_rate: Hertz,
+}
+
+impl<'a> I2s<'a, SPI2, MasterTransmit> {
+    pub fn ports_i2s2_transmit<M>(
+        _pb12_ws : GpioPin<GPIOB, Pin12, M, PinCnf2>,
+        _pb13_ck : GpioPin<GPIOB, Pin13, M, PinCnf2>,
+        _pb15_sd : GpioPin<GPIOB, Pin15, M, PinCnf2>) -> I2sPorts<SPI2, MasterTransmit>
+        where M : PinOutput + PinMode {
+            I2sPorts { i2s: PhantomData, mck: false }
+        }
+}
+
+impl<'a> I2s<'a, SPI2, MasterReceive> {
+    pub fn ports_i2s2_receive<M>(
+        _pb12_ws : GpioPin<GPIOB, Pin12, M, PinCnf2>,
+        _pb13_ck : GpioPin<GPIOB, Pin13, M, PinCnf2>,
+        _pb15_sd : GpioPin<GPIOB, Pin15, Input, PinCnf1>) -> I2sPorts<SPI2, MasterReceive>
+        where M : PinOutput + PinMode {
+            I2sPorts { i2s: PhantomData, mck: false }
+        }
+}
+
+impl<'a> I2s<'a, SPI3, MasterTransmit> {
+    pub fn ports_i2s3_transmit<M>(
+        _pa15_ws : GpioPin<GPIOA, Pin15, M, PinCnf2>,
+        _pb3_ck : GpioPin<GPIOB, Pin3, M, PinCnf2>,
+        _pb5_sd : GpioPin<GPIOB, Pin5, M, PinCnf2>,
+        _swj : &Swj<JtagReleased>) -> I2sPorts<SPI3, MasterTransmit>
+        where M : PinOutput + PinMode {
+            I2sPorts { i2s: PhantomData, mck: false }
+        }
+}
+
+impl<'a> I2s<'a, SPI3, MasterReceive> {
+    pub fn ports_i2s3_receive<M>(
+        _pa15_ws : GpioPin<GPIOA, Pin15, M, PinCnf2>,
+        _pb3_ck : GpioPin<GPIOB, Pin3, M, PinCnf2>,
+        _pb5_sd : GpioPin<GPIOB, Pin5, Input, PinCnf1>,
+        _swj : &Swj<JtagReleased>) -> I2sPorts<SPI3, MasterReceive>
+        where M : PinOutput + PinMode {
+            I2sPorts { i2s: PhantomData, mck: false }
+        }
+}
+
+/// Trait implemented by the I2S modes
+pub trait I2sConfig {
+    /// Returns the `I2SCFG` bits
+    fn i2scfg() -> u8;
+}
+
+impl I2sConfig for MasterTransmit { #[inline(always)] fn i2scfg() -> u8 { 0b10 } }
+impl I2sConfig for MasterReceive { #[inline(always)] fn i2scfg() -> u8 { 0b11 } }
+
+impl<'a, S, M> I2s<'a, S, M>
+where S: Any + SPI, M: I2sMode + I2sConfig {
+    /// Initializes the SPI module as an I2S master
+    /// 
+    /// # Arguments
+    /// - `standard`: The audio protocol.
+    /// - `format`: The data and channel length.
+    /// - `sample_rate`: The audio sample rate, typically 48 kHz or 44.1 kHz. The 
+    ///   closest possible rate is used, see `sample_rate`.
+    /// 
+    /// Returns `Err(Error::SampleRate)` if the sample rate cannot be derived from
+    /// the system clock.
+    pub fn new<F>(i2s: &'a S, ports: I2sPorts<S, M>, _rcc_periph: RccPeripheral<S, rcc::Enabled>,
+        standard: Standard, format: DataFormat, sample_rate: F, clocks: Clocks) -> Result<Self, Error> 
+        where F: Into<Hertz> {
+        let sample_rate = sample_rate.into().0;
+
+        // the bit clock is divided from the system clock, with the master clock 
+        // enabled it is always 256 times the sample rate
+        let frame_bits = match format {
+            DataFormat::Data16Channel16 => 32,
+            _ => 64,
+        };
+        let factor = if ports.mck { 256 } else { frame_bits };
+
+        let i2sclk = clocks.sysclk().0;
+        let bitclk = sample_rate.checked_mul(factor).ok_or(Error::SampleRate)?;
+        if bitclk == 0 {
+            return Err(Error::SampleRate);
+        }
+        let div = (i2sclk + bitclk / 2) / bitclk;
+        if !(4..=511).contains(&div) {
+            return Err(Error::SampleRate);
+        }
+
+        let (datlen, chlen) = match format {
+            DataFormat::Data16Channel16 => (0b00, false),
+            DataFormat::Data16Channel32 => (0b00, true),
+            DataFormat::Data24Channel32 => (0b01, true),
+            DataFormat::Data32Channel32 => (0b10, true),
+        };
+
+        let (i2sstd, pcmsync) = match standard {
+            Standard::Philips => (0b00, false),
+            Standard::Msb => (0b01, false),
+            Standard::Lsb => (0b10, false),
+            Standard::PcmShort => (0b11, false),
+            Standard::PcmLong => (0b11, true),
+        };
+
+        i2s.i2scfgr.write(|w| w.i2se().clear_bit());
+
+        i2s.i2spr.write(|w| unsafe {
+            w.i2sdiv()
+            .bits((div / 2) as u8)
+            .odd()
+            .bit(div & 1 != 0)
+            .mckoe()
+            .bit(ports.mck)
+        });
+
+        i2s.i2scfgr.write(|w| unsafe {
+            w.i2smod()
+            .set_bit()
+            .i2scfg()
+            .bits(M::i2scfg())
+            .i2sstd()
+            .bits(i2sstd)
+            .pcmsync()
+            .bit(pcmsync)
+            .ckpol()
+            .clear_bit()
+            .datlen()
+            .bits(datlen)
+            .chlen()
+            .bit(chlen)
+        });
+
+        Ok(I2s {
+            i2s,
+            mode: PhantomData,
+            format,
+            sample_rate: Hertz(i2sclk / (factor * div)),
+        })
+    }
+
+    /// Returns the sample rate which is actually used
+    pub fn sample_rate(&self) -> Hertz {
+        self.sample_rate
+    }
+
+    /// Returns the data format
+    pub fn format(&self) -> DataFormat {
+        self.format
+    }
+
+    /// Starts generating the clocks
+    pub fn enable(&self) {
+        self.i2s.i2scfgr.modify(|_, w| w.i2se().set_bit())
+    }
+
+    /// Stops generating the clocks
+    /// 
+    /// **NOTE** To avoid an incomplete frame, this should be called right after
+    /// the last half word of a frame has been transferred.
+    pub fn disable(&self) {
+        self.i2s.i2scfgr.modify(|_, w| w.i2se().clear_bit())
+    }
+
+    /// Returns the channel the next half word belongs to
+    pub fn channel(&self) -> Channel {
+        if self.i2s.sr.read().chside().bit_is_set() { Channel::Right } else { Channel::Left }
+    }
+
+    /// Enables the transmit buffer empty / receive buffer not empty and error interrupts
+    pub fn listen(&self) {
+        self.i2s.cr2.modify(|_, w| w.txeie().set_bit().rxneie().set_bit().errie().set_bit());
+    }
+
+    #[inline(always)]
+    fn dr(&self) -> u32 {
+        &self.i2s.dr as *const _ as u32
+    }
+}
+
+impl<'a, S> I2s<'a, S, MasterTransmit>
+where S: Any + SPI {
+    /// Writes the next half word
+    pub fn write(&mut self, data: u16) -> nb::Result<(), Error> {
+        if self.i2s.sr.read().txe().bit_is_set() {
+            unsafe { common::write_register(&self.i2s.dr, data) }
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Streams the buffer to the interface using DMA
+    /// 
+    /// The buffer is transmitted in a loop, `poll` returns the half of the buffer
+    /// which has been transmitted and can be refilled. The interface is enabled.
+    pub fn stream_transmit(self, mut channel: DmaChannel<S::DmaTx, S::Dma>, buffer: &'static mut [u16]) 
+        -> I2sStream<'a, S, MasterTransmit, S::DmaTx> {
+        atomic::compiler_fence(Ordering::Release);
+
+        channel.start_with(self.dr(), buffer.as_ptr() as u32, buffer.len(), dma::Config {
+            dir: dma::Direction::MemoryToPeripheral,
+            minc: true,
+            size: dma::DataSize::Bits16,
+            circular: true,
+        });
+        self.i2s.cr2.modify(|_, w| w.txdmaen().set_bit());
+        self.enable();
+
+        I2sStream {
+            i2s: self,
+            channel,
+            buffer,
+        }
+    }
+}
+
+impl<'a, S> I2s<'a, S, MasterReceive>
+where S: Any + SPI {
+    /// Reads the next half word
+    pub fn read(&mut self) -> nb::Result<u16, Error> {
+        let sr = self.i2s.sr.read();
+
+        if sr.ovr().bit_is_set() {
+            Err(nb::Error::Other(Error::Overrun))
+        } else if sr.rxne().bit_is_set() {
+            Ok(unsafe { ptr::read_volatile(&self.i2s.dr as *const _ as *const u16) })
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Streams the received data into the buffer using DMA
+    /// 
+    /// The buffer is filled in a loop, `poll` returns the half of the buffer
+    /// which has been filled and can be processed. The interface is enabled.
+    pub fn stream_receive(self, mut channel: DmaChannel<S::DmaRx, S::Dma>, buffer: &'static mut [u16]) 
+        -> I2sStream<'a, S, MasterReceive, S::DmaRx> {
+        atomic::compiler_fence(Ordering::Release);
+
+        channel.start_with(self.dr(), buffer.as_mut_ptr() as u32, buffer.len(), dma::Config {
+            dir: dma::Direction::PeripheralToMemory,
+            minc: true,
+            size: dma::DataSize::Bits16,
+            circular: true,
+        });
+        self.i2s.cr2.modify(|_, w| w.rxdmaen().set_bit());
+        self.enable();
+
+        I2sStream {
+            i2s: self,
+            channel,
+            buffer,
+        }
+    }
+}
+
+/// A circular DMA transfer between an I2S interface and a buffer
+pub struct I2sStream<'a, S, M, C>
+where S: Any + SPI, M: I2sMode, C: dma::Channels + dma::ChannelNr {
+    i2s: I2s<'a, S, M>,
+    channel: DmaChannel<C, S::Dma>,
+    buffer: &'static mut [u16],
+}
+
+impl<'a, S, M, C> I2sStream<'a, S, M, C>
+where S: Any + SPI, M: I2sMode + I2sConfig, C: dma::Channels + dma::ChannelNr {
+    /// Returns the half of the buffer which is currently not used by the DMA controller
+    /// 
+    /// Returns `Err(nb::Error::WouldBlock)` until the DMA controller has finished 
+    /// the next half of the buffer. The returned half has to be processed before
+    /// the DMA controller finishes the other half.
+    pub fn poll(&mut self) -> nb::Result<Half, Error> {
+        if self.channel.has_error() {
+            return Err(nb::Error::Other(Error::TransferError));
+        }
+
+        let half = if self.channel.is_complete() {
+            Half::Second
+        } else if self.channel.is_half_complete() {
+            Half::First
+        } else {
+            return Err(nb::Error::WouldBlock);
+        };
+
+        self.channel.clear_complete();
+        atomic::compiler_fence(Ordering::Acquire);
+
+        Ok(half)
+    }
+
+    /// Returns the given half of the buffer
+    pub fn half_mut(&mut self, half: Half) -> &mut [u16] {
+        let len = self.buffer.len() / 2;
+        let (first, second) = self.buffer.split_at_mut(len);
+        match half {
+            Half::First => first,
+            Half::Second => second,
+        }
+    }
+
+    /// Stops the stream and returns the interface, the DMA channel and the buffer
+    pub fn stop(mut self) -> (I2s<'a, S, M>, DmaChannel<C, S::Dma>, &'static mut [u16]) {
+        self.i2s.disable();
+        self.i2s.i2s.cr2.modify(|_, w| w.txdmaen().clear_bit().rxdmaen().clear_bit());
+        self.channel.stop();
+
+        atomic::compiler_fence(Ordering::Acquire);
+
+        (self.i2s, self.channel, self.buffer)
+    }
+}
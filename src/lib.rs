@@ -27,15 +27,21 @@ pub mod examples;
 pub mod prelude;
 
 pub mod i2c;
+pub mod smbus;
 pub mod rcc;
 pub mod afio;
 pub mod gpio;
 pub mod spi;
+#[cfg(feature = "high-density")]
+pub mod i2s;
 pub mod usart;
 pub mod time;
 pub mod flash;
+pub mod dma;
+pub mod bus;
 
 pub use flash::Flash;
+pub use dma::Dma;
 pub use usart::Usart;
 pub use spi::Spi;
 pub use rcc::Rcc;
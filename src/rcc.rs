@@ -2,12 +2,16 @@
 use common;
 
 use core::cmp;
+use core::fmt;
 use core::marker::PhantomData;
 
-use stm32f103xx::{rcc, RCC, GPIOA, GPIOB, GPIOC, GPIOD, I2C1, I2C2, TIM2, SPI1, SPI2, USART1, USART2, AFIO};
+use stm32f103xx::{rcc, RCC, GPIOA, GPIOB, GPIOC, GPIOD, I2C1, I2C2, TIM2, SPI1, SPI2, USART1, USART2, AFIO, DMA1, PWR, BKP};
+#[cfg(feature = "high-density")]
+use stm32f103xx::{SPI3, DMA2};
 
 use time::Hertz;
 use flash::ACR;
+use gpio::{GpioPin, Pin8, PinCnf2, PinOutput, PinMode};
 
 type_states!(IsEnabled, (Disabled, Enabled));
 
@@ -22,7 +26,10 @@ macro_rules! rcc_macro {
                 unsafe { (*RCC::ptr()).$apbenr.modify(|_, w| w.$enable().enabled()); }
                 RccPeripheral(self.0, PhantomData)
             }
+        }
 
+        impl<S> RccPeripheral<$name, S> where S: IsEnabled {
+            /// Resets all registers of the peripheral to their reset values
             #[inline(always)]
             pub fn reset(self) -> Self {
                 unsafe {
@@ -34,6 +41,18 @@ macro_rules! rcc_macro {
         }
         
     };
+    // peripherals without a reset register (AHB)
+    ($name:ident, $ahbenr:ident, $enable:ident) => {
+
+        impl RccPeripheral<$name,Disabled> {
+            #[inline(always)]
+            pub fn enable(self) -> RccPeripheral<$name, Enabled> {
+                unsafe { (*RCC::ptr()).$ahbenr.modify(|_, w| w.$enable().set_bit()); }
+                RccPeripheral(self.0, PhantomData)
+            }
+        }
+
+    };
 }
 
 rcc_macro!(USART1, apb2enr, usart1en, apb2rstr, usart1rst);
@@ -48,22 +67,30 @@ rcc_macro!(GPIOC, apb2enr, iopcen, apb2rstr, iopcrst);
 rcc_macro!(GPIOD, apb2enr, iopden, apb2rstr, iopdrst);
 rcc_macro!(SPI1, apb2enr, spi1en, apb2rstr, spi1rst);
 rcc_macro!(SPI2, apb1enr, spi2en, apb1rstr, spi2rst);
+rcc_macro!(DMA1, ahbenr, dma1en);
+rcc_macro!(PWR, apb1enr, pwren, apb1rstr, pwrrst);
+rcc_macro!(BKP, apb1enr, bkpen, apb1rstr, bkprst);
+#[cfg(feature = "high-density")]
+rcc_macro!(SPI3, apb1enr, spi3en, apb1rstr, spi3rst);
+#[cfg(feature = "high-density")]
+rcc_macro!(DMA2, ahbenr, dma2en);
 
 pub struct Rcc{
     pub cfgr: CFGR,
     pub peripherals: RccPeripherals,
+    pub mco: Mco,
+    pub bdcr: BDCR,
+    pub csr: CSR,
 }
 
 impl Rcc {
     pub fn new(_r: RCC) -> Self {
         Rcc{
-            cfgr: CFGR{
-                hclk: None,
-                pclk1: None,
-                pclk2: None,
-                sysclk: None,
-            },
-            peripherals: Rcc::get_peripherals()
+            cfgr: CFGR::new(),
+            peripherals: Rcc::get_peripherals(),
+            mco: Mco(()),
+            bdcr: BDCR(()),
+            csr: CSR(()),
         }
     }
 
@@ -75,9 +102,10 @@ impl Rcc {
 }
 
 macro_rules! peripherals {
-    ( $(($periph:ident, $name:ident)),* ) => {
+    ( $($(#[$attr:meta])* ($periph:ident, $name:ident)),* ) => {
         pub struct RccPeripherals {
             $( 
+                $(#[$attr])*
                 pub $name : RccPeripheral<$periph, Disabled>,
             )*
         }
@@ -87,6 +115,7 @@ macro_rules! peripherals {
             fn get_peripherals() -> RccPeripherals {
                 RccPeripherals {
                     $(
+                        $(#[$attr])*
                         $name: RccPeripheral(PhantomData, PhantomData),
                     )*
                 }
@@ -107,21 +136,370 @@ peripherals!{
     (GPIOC, iopc),
     (GPIOD, iopd),
     (SPI1, spi1),
-    (SPI2, spi2)
+    (SPI2, spi2),
+    (DMA1, dma1),
+    (PWR, pwr),
+    (BKP, bkp),
+    #[cfg(feature = "high-density")]
+    (SPI3, spi3),
+    #[cfg(feature = "high-density")]
+    (DMA2, dma2)
+}
+
+/// Clock provided on the MCO pin
+#[derive(PartialEq, Clone, Copy)]
+pub enum McoSource {
+    Sysclk,
+    Hsi,
+    Hse,
+    /// PLL output divided by two
+    PllDiv2,
+}
+
+impl McoSource {
+    #[inline(always)]
+    fn bits(self) -> u8 {
+        match self {
+            McoSource::Sysclk => 0b100,
+            McoSource::Hsi => 0b101,
+            McoSource::Hse => 0b110,
+            McoSource::PllDiv2 => 0b111,
+        }
+    }
+}
+
+/// Microcontroller clock output, which is provided on PA8
+/// 
+/// # Example
+/// 
+/// ```
+/// let pa8 = pinsa.8.set_output_50MHz().set_alt_output_push_pull();
+/// let mco = rcc.mco.enable(pa8, McoSource::Sysclk);
+/// ```
+pub struct Mco(());
+
+impl Mco {
+    /// Outputs the clock source on PA8, the output frequency should not exceed 
+    /// 50 MHz
+    pub fn enable<M>(self, pa8: GpioPin<GPIOA, Pin8, M, PinCnf2>, source: McoSource) -> McoOutput<M> 
+        where M: PinOutput + PinMode {
+        let mut mco = McoOutput {
+            mco: self,
+            pin: pa8,
+        };
+        mco.set_source(source);
+
+        mco
+    }
+}
+
+/// Enabled clock output, owning PA8 until it is disabled
+pub struct McoOutput<M> where M: PinOutput + PinMode {
+    mco: Mco,
+    pin: GpioPin<GPIOA, Pin8, M, PinCnf2>,
+}
+
+impl<M> McoOutput<M> where M: PinOutput + PinMode {
+    /// Changes the clock provided on PA8
+    pub fn set_source(&mut self, source: McoSource) {
+        unsafe { (*RCC::ptr()).cfgr.modify(|_, w| w.mco().bits(source.bits())) };
+    }
+
+    /// Stops the clock output and releases PA8
+    pub fn disable(self) -> (Mco, GpioPin<GPIOA, Pin8, M, PinCnf2>) {
+        unsafe { (*RCC::ptr()).cfgr.modify(|_, w| w.mco().bits(0b000)) };
+        (self.mco, self.pin)
+    }
+}
+
+/// Frequency of the LSE crystal
+const LSE: u32 = 32_768; // Hz
+
+/// Typical frequency of the LSI oscillator, which varies between 30 kHz and 60 kHz
+const LSI: u32 = 40_000; // Hz
+
+/// Number of polling iterations until the LSE has to be ready, its startup may
+/// take more than a second
+const LSE_STARTUP_TIMEOUT: u32 = 0x80_0000;
+
+/// Backup domain control, see `BackupDomain`
+pub struct BDCR(());
+
+impl BDCR {
+    /// Disables the write protection of the backup domain (PWR DBP), which contains
+    /// the LSE, the RTC and the backup registers
+    pub fn enable_access(self, pwr: &PWR, _pwr_periph: &RccPeripheral<PWR, Enabled>, _bkp_periph: &RccPeripheral<BKP, Enabled>) -> BackupDomain {
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+        BackupDomain(())
+    }
+}
+
+/// Write access to the backup domain
+/// 
+/// # Example
+/// 
+/// ```
+/// let mut backup = rcc.bdcr.enable_access(&dp.PWR, &pwr, &bkp);
+/// let lse = backup.enable_lse(false).unwrap();
+/// let rtc_clock = backup.set_rtc_clock(lse).unwrap();
+/// 
+/// // switch to the LSI, `lse` and `rtc_clock` cannot be used after the reset
+/// backup.reset();
+/// let rtc_clock = backup.set_rtc_clock(rcc.csr.enable_lsi()).unwrap();
+/// ```
+pub struct BackupDomain(());
+
+impl BackupDomain {
+    #[inline(always)]
+    fn bdcr<'a>(&self) -> &'a rcc::BDCR {
+        unsafe { &(*RCC::ptr()).bdcr }
+    }
+
+    /// Starts the 32.768 kHz LSE oscillator, or uses an external clock applied to 
+    /// OSC32_IN if `bypass` is set
+    /// 
+    /// The LSE keeps running from VBAT while the device is powered down, it is 
+    /// only stopped by a backup domain reset. Blocks until the LSE is ready, 
+    /// `ClockError::LseNotReady` is returned if it does not start within about a
+    /// second.
+    pub fn enable_lse<'a>(&'a self, bypass: bool) -> Result<Lse<'a>, ClockError> {
+        let bdcr = self.bdcr();
+
+        if bdcr.read().lserdy().bit_is_clear() {
+            // LSEBYP can only be written while the LSE is disabled
+            bdcr.modify(|_, w| w.lseon().clear_bit());
+            bdcr.modify(|_, w| w.lsebyp().bit(bypass));
+            bdcr.modify(|_, w| w.lseon().set_bit());
+
+            let mut timeout = LSE_STARTUP_TIMEOUT;
+            while bdcr.read().lserdy().bit_is_clear() {
+                timeout -= 1;
+                if timeout == 0 {
+                    bdcr.modify(|_, w| w.lseon().clear_bit());
+                    return Err(ClockError::LseNotReady);
+                }
+            }
+        }
+
+        Ok(Lse(PhantomData))
+    }
+
+    /// Selects the clock of the RTC and enables it
+    /// 
+    /// The selection can only be changed by a backup domain reset, 
+    /// `ClockError::RtcClockLocked` is returned if another source has been selected
+    /// before.
+    pub fn set_rtc_clock<'a, S>(&'a self, source: S) -> Result<RtcClock<'a, S>, ClockError> 
+        where S: RtcClockSource {
+        let bdcr = self.bdcr();
+
+        match bdcr.read().rtcsel().bits() {
+            0b00 => bdcr.modify(|_, w| w.rtcsel().bits(S::rtcsel())),
+            bits if bits == S::rtcsel() => {},
+            _ => return Err(ClockError::RtcClockLocked),
+        }
+        bdcr.modify(|_, w| w.rtcen().set_bit());
+
+        Ok(RtcClock(source, PhantomData))
+    }
+
+    /// Resets the backup domain: the LSE and the RTC are stopped, the RTC clock
+    /// selection and the backup registers are cleared
+    /// 
+    /// The `Lse` and `RtcClock` tokens borrow the backup domain, so they have to 
+    /// be dropped before. The LSI and HSE sources can be taken back using 
+    /// `RtcClock::free`.
+    pub fn reset(&mut self) {
+        let bdcr = self.bdcr();
+
+        bdcr.modify(|_, w| w.bdrst().set_bit());
+        bdcr.modify(|_, w| w.bdrst().clear_bit());
+    }
+}
+
+/// Control and status register of the RCC, containing the LSI control and the
+/// reset flags
+pub struct CSR(());
+
+/// Causes of the last reset, several flags may be set at once
+/// 
+/// A power-on reset also sets the `pin` flag, as NRST is driven low during the 
+/// reset.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ResetReason {
+    /// Power-on or power-down reset
+    pub power_on: bool,
+    /// NRST pin
+    pub pin: bool,
+    /// Software reset (`SYSRESETREQ`)
+    pub software: bool,
+    /// Independent watchdog
+    pub independent_watchdog: bool,
+    /// Window watchdog
+    pub window_watchdog: bool,
+    /// Low-power management reset, caused by entering Standby or Stop mode while
+    /// the option bytes prohibit it
+    pub low_power: bool,
+}
+
+impl CSR {
+    /// Returns the causes of the last reset and clears the flags, so the next 
+    /// reset is reported correctly
+    /// 
+    /// # Example
+    /// 
+    /// ```
+    /// let mut rcc = Rcc::new(dp.RCC);
+    /// let reason = rcc.csr.reset_reason();
+    /// if reason.independent_watchdog { /* log the watchdog reset */ }
+    /// let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    /// ```
+    pub fn reset_reason(&mut self) -> ResetReason {
+        let rcc = unsafe { &*RCC::ptr() };
+        let csr = rcc.csr.read();
+
+        let reason = ResetReason {
+            power_on: csr.porrstf().bit_is_set(),
+            pin: csr.pinrstf().bit_is_set(),
+            software: csr.sftrstf().bit_is_set(),
+            independent_watchdog: csr.iwdgrstf().bit_is_set(),
+            window_watchdog: csr.wwdgrstf().bit_is_set(),
+            low_power: csr.lpwrrstf().bit_is_set(),
+        };
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+        reason
+    }
+
+    /// Starts the LSI oscillator, which clocks the independent watchdog and can 
+    /// be used by the RTC
+    /// 
+    /// Blocks until the LSI is ready, which takes at most 85 µs.
+    pub fn enable_lsi(&mut self) -> Lsi {
+        let rcc = unsafe { &*RCC::ptr() };
+
+        rcc.csr.modify(|_, w| w.lsion().set_bit());
+        while rcc.csr.read().lsirdy().bit_is_clear() {}
+
+        Lsi(())
+    }
+}
+
+/// Proof that the LSE is running, valid until the backup domain is reset
+pub struct Lse<'a>(PhantomData<&'a BackupDomain>);
+
+/// Proof that the LSI is running
+pub struct Lsi(());
+
+/// Proof that the HSE is running, see `Clocks::hse_div128`
+pub struct HseDiv128(u32);
+
+/// Oscillator which can clock the RTC
+pub trait RtcClockSource {
+    /// Value of `RTCSEL`
+    fn rtcsel() -> u8;
+    fn freq(&self) -> Hertz;
+}
+
+impl<'a> RtcClockSource for Lse<'a> {
+    fn rtcsel() -> u8 { 0b01 }
+    fn freq(&self) -> Hertz { Hertz(LSE) }
+}
+
+impl RtcClockSource for Lsi {
+    fn rtcsel() -> u8 { 0b10 }
+    fn freq(&self) -> Hertz { Hertz(LSI) }
+}
+
+impl RtcClockSource for HseDiv128 {
+    fn rtcsel() -> u8 { 0b11 }
+    fn freq(&self) -> Hertz { Hertz(self.0 / 128) }
+}
+
+/// Proof that the RTC is clocked by the source `S`, valid until the backup 
+/// domain is reset
+pub struct RtcClock<'a, S>(S, PhantomData<&'a BackupDomain>) where S: RtcClockSource;
+
+impl<'a, S> RtcClock<'a, S> where S: RtcClockSource {
+    /// Returns the nominal frequency of the RTC clock
+    pub fn freq(&self) -> Hertz {
+        self.0.freq()
+    }
+
+    /// Releases the source, the RTC keeps running from it
+    pub fn free(self) -> S {
+        self.0
+    }
 }
 
 // from stm32f103xx-hal
 
 const HSI: u32 = 8_000_000; // Hz
 
+/// Maximum frequency of SYSCLK, HCLK and PCLK2
+const SYSCLK_MAX: u32 = 72_000_000; // Hz
+
+/// Maximum frequency of PCLK1
+const PCLK1_MAX: u32 = 36_000_000; // Hz
+
+/// Frequency range of the HSE oscillator
+const HSE_MIN: u32 = 4_000_000; // Hz
+const HSE_MAX: u32 = 16_000_000; // Hz
+
+/// Maximum frequency of the ADC clock
+const ADCCLK_MAX: u32 = 14_000_000; // Hz
+
+/// Frequency of the USB clock
+const USBCLK: u32 = 48_000_000; // Hz
+
+/// Number of polling iterations until the HSE has to be ready
+const HSE_STARTUP_TIMEOUT: u32 = 0x10000;
+
 pub struct CFGR {
+    hse: Option<u32>,
+    hse_bypass: bool,
     hclk: Option<u32>,
     pclk1: Option<u32>,
     pclk2: Option<u32>,
     sysclk: Option<u32>,
+    adcclk: Option<u32>,
+    usb: bool,
+    css: bool,
 }
 
 impl CFGR {
+    fn new() -> Self {
+        CFGR {
+            hse: None,
+            hse_bypass: false,
+            hclk: None,
+            pclk1: None,
+            pclk2: None,
+            sysclk: None,
+            adcclk: None,
+            usb: false,
+            css: false,
+        }
+    }
+
+    /// Uses the external oscillator (HSE) with the given frequency as the clock 
+    /// source, which has to be between 4 MHz and 16 MHz
+    /// 
+    /// Without a requested `sysclk`, the HSE is used as the system clock directly.
+    pub fn use_hse<F>(mut self, freq: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        self.hse = Some(freq.into().0);
+        self
+    }
+
+    /// Bypasses the HSE oscillator, for an external clock signal applied to OSC_IN
+    pub fn bypass_hse(mut self) -> Self {
+        self.hse_bypass = true;
+        self
+    }
+
     pub fn hclk<F>(mut self, freq: F) -> Self
     where
         F: Into<Hertz>,
@@ -154,78 +532,211 @@ impl CFGR {
         self
     }
 
-    pub fn freeze(self, acr: &mut ACR) -> Clocks {
-        // TODO ADC & USB clocks
+    /// Sets the maximum frequency of the ADC clock, which is at most 14 MHz
+    pub fn adcclk<F>(mut self, freq: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        self.adcclk = Some(freq.into().0);
+        self
+    }
 
-        let pllmul = (4 * self.sysclk.unwrap_or(HSI) + HSI) / HSI / 2;
-        let pllmul = cmp::min(cmp::max(pllmul, 2), 16);
-        let pllmul_bits = if pllmul == 2 {
-            None
-        } else {
-            Some(pllmul as u8 - 2)
+    /// Provides the 48 MHz USB clock, which requires the PLL to run at 48 MHz or
+    /// 72 MHz from the HSE (`use_hse`)
+    pub fn usb(mut self) -> Self {
+        self.usb = true;
+        self
+    }
+
+    /// Enables the clock security system, which switches the system clock to the
+    /// HSI if the HSE fails. Only takes effect if the HSE is used.
+    /// 
+    /// A failure raises the NMI, which has to be acknowledged using 
+    /// `clear_css_interrupt`. The reduced frequencies are returned by 
+    /// `Clocks::degraded`.
+    pub fn enable_css(mut self) -> Self {
+        self.css = true;
+        self
+    }
+
+    /// Selects the PLL multiplier for the requested system clock
+    /// 
+    /// The PLL is fed with HSI / 2 or, if the HSE is used, with HSE or HSE / 2 
+    /// (`PLLXTPRE`). The frequency closest to the requested one is selected, not 
+    /// exceeding 72 MHz. Returns `None` if the oscillator is used directly.
+    fn pll(&self) -> Result<(Option<Pll>, u32), ClockError> {
+        let osc = self.hse.unwrap_or(HSI);
+        let sysclk = self.sysclk.unwrap_or(osc);
+
+        if sysclk == 0 || sysclk > SYSCLK_MAX {
+            return Err(ClockError::SysclkOutOfRange);
+        }
+        if sysclk == osc {
+            return Ok((None, osc));
+        }
+
+        // PLL input frequency and PLLXTPRE
+        let hse_inputs;
+        let hsi_inputs = [(HSI / 2, false)];
+        let inputs: &[(u32, bool)] = match self.hse {
+            Some(hse) => {
+                hse_inputs = [(hse, false), (hse / 2, true)];
+                &hse_inputs
+            },
+            None => &hsi_inputs,
         };
 
-        let sysclk = pllmul * HSI / 2;
+        let mut best: Option<(Pll, u32)> = None;
+        for &(input, xtpre) in inputs.iter() {
+            let mut mul = ((sysclk + input / 2) / input).clamp(2, 16);
+            while mul > 2 && mul * input > SYSCLK_MAX {
+                mul -= 1;
+            }
+
+            let freq = mul * input;
+            if best.map(|(_, f)| freq.abs_diff(sysclk) < f.abs_diff(sysclk)).unwrap_or(true) {
+                best = Some((Pll { mul: mul as u8, xtpre }, freq));
+            }
+        }
+
+        let (pll, freq) = best.unwrap();
+        Ok((Some(pll), freq))
+    }
+
+    /// Computes the prescaler settings and the resulting frequencies without 
+    /// touching any registers
+    /// 
+    /// Returns an error if the requested frequencies cannot be provided or exceed
+    /// the limits of the device.
+    pub fn solve(&self) -> Result<ClockConfig, ClockError> {
+        if let Some(hse) = self.hse {
+            if !(HSE_MIN..=HSE_MAX).contains(&hse) {
+                return Err(ClockError::HseOutOfRange);
+            }
+        }
 
-        assert!(sysclk < 72_000_000);
+        let (pll, sysclk) = self.pll()?;
 
-        let hpre_bits = self.hclk
-            .map(|hclk| match sysclk / hclk {
-                0 => unreachable!(),
-                1 => 0b0111,
+        let hpre_bits = match self.hclk {
+            Some(hclk) if hclk == 0 || hclk > sysclk => return Err(ClockError::HclkOutOfRange),
+            // the lowest division ratio which does not exceed the requested frequency
+            Some(hclk) => match sysclk.div_ceil(hclk) {
+                0 | 1 => 0b0111,
                 2 => 0b1000,
-                3...5 => 0b1001,
-                6...11 => 0b1010,
-                12...39 => 0b1011,
-                40...95 => 0b1100,
-                96...191 => 0b1101,
-                192...383 => 0b1110,
+                3..=4 => 0b1001,
+                5..=8 => 0b1010,
+                9..=16 => 0b1011,
+                17..=64 => 0b1100,
+                65..=128 => 0b1101,
+                129..=256 => 0b1110,
                 _ => 0b1111,
-            })
-            .unwrap_or(0b0111);
+            },
+            None => 0b0111,
+        };
 
-        let hclk = sysclk / (1 << (hpre_bits - 0b0111));
+        let hclk = sysclk / hpre_div(hpre_bits);
 
-        assert!(hclk < 72_000_000);
+        let ppre1_bits = match self.pclk1 {
+            Some(pclk1) if pclk1 == 0 || pclk1 > hclk => return Err(ClockError::Pclk1OutOfRange),
+            Some(pclk1) => ppre_bits(hclk.div_ceil(pclk1)),
+            // the lowest prescaler respecting the limit of the APB1
+            None => ppre_bits(hclk.div_ceil(PCLK1_MAX)),
+        };
 
-        let ppre1_bits = self.pclk1
-            .map(|pclk1| match hclk / pclk1 {
-                0 => unreachable!(),
-                1 => 0b011,
-                2 => 0b100,
-                3...5 => 0b101,
-                6...11 => 0b110,
-                _ => 0b111,
-            })
-            .unwrap_or(0b011);
+        let pclk1 = hclk / ppre_div(ppre1_bits);
 
-        let ppre1 = 1 << (ppre1_bits - 0b011);
-        let pclk1 = hclk / (ppre1 as u32);
+        if pclk1 > PCLK1_MAX {
+            return Err(ClockError::Pclk1OutOfRange);
+        }
 
-        assert!(pclk1 <= 36_000_000);
+        let ppre2_bits = match self.pclk2 {
+            Some(pclk2) if pclk2 == 0 || pclk2 > hclk => return Err(ClockError::Pclk2OutOfRange),
+            Some(pclk2) => ppre_bits(hclk.div_ceil(pclk2)),
+            None => 0b011,
+        };
 
-        let ppre2_bits = self.pclk2
-            .map(|pclk2| match hclk / pclk2 {
-                0 => unreachable!(),
-                1 => 0b011,
-                2 => 0b100,
-                3...5 => 0b101,
-                6...11 => 0b110,
-                _ => 0b111,
-            })
-            .unwrap_or(0b011);
+        let pclk2 = hclk / ppre_div(ppre2_bits);
+
+        // USB clock: PLL / 1.5 (USBPRE = 0) or PLL / 1 (USBPRE = 1)
+        let usbpre = if self.usb {
+            // the HSI is not accurate enough for full speed USB
+            if self.hse.is_none() {
+                return Err(ClockError::UsbRequiresHse);
+            }
+            match pll.map(|_| sysclk) {
+                Some(72_000_000) => Some(false),
+                Some(48_000_000) => Some(true),
+                _ => return Err(ClockError::UsbClockImpossible),
+            }
+        } else {
+            None
+        };
+
+        // ADC clock: PCLK2 / 2, 4, 6 or 8
+        let adcclk_max = cmp::min(self.adcclk.unwrap_or(ADCCLK_MAX), ADCCLK_MAX);
+        let adcpre = match (0..4u8).find(|&bits| pclk2 / (2 * (bits as u32 + 1)) <= adcclk_max) {
+            Some(adcpre) => adcpre,
+            None => return Err(ClockError::AdcclkOutOfRange),
+        };
+        let adcclk = pclk2 / adcpre_div(adcpre);
+
+        Ok(ClockConfig {
+            sysclk,
+            hclk,
+            pclk1,
+            pclk2,
+            adcclk,
+            usbclk: usbpre.map(|_| USBCLK),
+            pll,
+            hpre_bits,
+            ppre1_bits,
+            ppre2_bits,
+            adcpre_bits: adcpre,
+            usbpre: usbpre == Some(true),
+        })
+    }
+
+    /// Applies the clock configuration
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if the configuration is invalid, see `try_freeze`.
+    pub fn freeze(self, acr: &mut ACR) -> Clocks {
+        match self.try_freeze(acr) {
+            Ok(clocks) => clocks,
+            Err(err) => panic!("invalid clock configuration: {}", err),
+        }
+    }
+
+    /// Applies the clock configuration, returns an error without changing the 
+    /// clocks if the configuration is invalid (see `solve`) or if the HSE does not 
+    /// become ready.
+    pub fn try_freeze(self, acr: &mut ACR) -> Result<Clocks, ClockError> {
+        let config = self.solve()?;
+        let rcc = unsafe { &*RCC::ptr() };
 
-        let ppre2 = 1 << (ppre2_bits - 0b011);
-        let pclk2 = hclk / (ppre2 as u32);
+        if self.hse.is_some() {
+            rcc.cr.modify(|_, w| w.hsebyp().bit(self.hse_bypass));
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
 
-        assert!(pclk2 < 72_000_000);
+            let mut timeout = HSE_STARTUP_TIMEOUT;
+            while rcc.cr.read().hserdy().bit_is_clear() {
+                timeout -= 1;
+                if timeout == 0 {
+                    rcc.cr.modify(|_, w| w.hseon().clear_bit());
+                    return Err(ClockError::HseNotReady);
+                }
+            }
+
+            rcc.cr.modify(|_, w| w.csson().bit(self.css));
+        }
 
         // adjust flash wait states
         unsafe {
             acr.acr().write(|w| {
-                w.latency().bits(if sysclk <= 24_000_000 {
+                w.latency().bits(if config.sysclk <= 24_000_000 {
                     0b000
-                } else if sysclk <= 48_000_000 {
+                } else if config.sysclk <= 48_000_000 {
                     0b001
                 } else {
                     0b010
@@ -233,52 +744,224 @@ impl CFGR {
             })
         }
 
-        let rcc = unsafe { &*RCC::ptr() };
-        if let Some(pllmul_bits) = pllmul_bits {
+        // keep the clock output enabled using `Mco`
+        let mco = rcc.cfgr.read().mco().bits();
+
+        if let Some(pll) = config.pll {
             // use PLL as source
 
-            rcc.cfgr.write(|w| unsafe { w.pllmul().bits(pllmul_bits) });
+            rcc.cfgr.write(|w| unsafe { w.mco().bits(mco)
+                .pllmul().bits(pll.mul - 2)
+                .pllsrc().bit(self.hse.is_some())
+                .pllxtpre().bit(pll.xtpre) });
 
-            rcc.cr.write(|w| w.pllon().enabled());
+            rcc.cr.modify(|_, w| w.pllon().enabled());
 
             while rcc.cr.read().pllrdy().is_unlocked() {}
 
             rcc.cfgr.modify(|_, w| unsafe {
                 w.ppre2()
-                    .bits(ppre2_bits)
+                    .bits(config.ppre2_bits)
                     .ppre1()
-                    .bits(ppre1_bits)
+                    .bits(config.ppre1_bits)
                     .hpre()
-                    .bits(hpre_bits)
+                    .bits(config.hpre_bits)
+                    .adcpre()
+                    .bits(config.adcpre_bits)
+                    .usbpre()
+                    .bit(config.usbpre)
                     .sw()
                     .pll()
             });
+        } else if self.hse.is_some() {
+            // use HSE as source
+
+            rcc.cfgr.write(|w| unsafe {
+                w.ppre2()
+                    .bits(config.ppre2_bits)
+                    .ppre1()
+                    .bits(config.ppre1_bits)
+                    .hpre()
+                    .bits(config.hpre_bits)
+                    .adcpre()
+                    .bits(config.adcpre_bits)
+                    .mco()
+                    .bits(mco)
+                    .sw()
+                    .hse()
+            });
         } else {
             // use HSI as source
 
             rcc.cfgr.write(|w| unsafe {
                 w.ppre2()
-                    .bits(ppre2_bits)
+                    .bits(config.ppre2_bits)
                     .ppre1()
-                    .bits(ppre1_bits)
+                    .bits(config.ppre1_bits)
                     .hpre()
-                    .bits(hpre_bits)
+                    .bits(config.hpre_bits)
+                    .adcpre()
+                    .bits(config.adcpre_bits)
+                    .mco()
+                    .bits(mco)
                     .sw()
                     .hsi()
             });
         }
 
-        Clocks {
-            hclk: Hertz(hclk),
-            pclk1: Hertz(pclk1),
-            pclk2: Hertz(pclk2),
-            ppre1,
-            ppre2,
-            sysclk: Hertz(sysclk),
+        Ok(Clocks {
+            hclk: Hertz(config.hclk),
+            pclk1: Hertz(config.pclk1),
+            pclk2: Hertz(config.pclk2),
+            ppre1: ppre_div(config.ppre1_bits) as u8,
+            ppre2: ppre_div(config.ppre2_bits) as u8,
+            sysclk: Hertz(config.sysclk),
+            adcclk: Hertz(config.adcclk),
+            usbclk: config.usbclk.map(Hertz),
+            hse: self.hse.map(Hertz),
+            sws: match config.pll {
+                Some(_) => SWS_PLL,
+                None if self.hse.is_some() => SWS_HSE,
+                None => SWS_HSI,
+            },
+        })
+    }
+}
+
+/// Returns the division ratio of the AHB prescaler bits
+fn hpre_div(bits: u8) -> u32 {
+    match bits {
+        0b1000..=0b1011 => 1 << (bits - 0b0111),
+        // 64 to 512, skipping 32
+        0b1100..=0b1111 => 1 << (bits - 0b0110),
+        _ => 1,
+    }
+}
+
+/// Returns the division ratio of the APB prescaler bits
+fn ppre_div(bits: u8) -> u32 {
+    if bits & 0b100 != 0 { 1 << (bits - 0b011) } else { 1 }
+}
+
+/// Returns the division ratio of the ADC prescaler bits
+fn adcpre_div(bits: u8) -> u32 {
+    2 * (bits as u32 + 1)
+}
+
+/// Acknowledges the interrupt of the clock security system, has to be called from
+/// the NMI handler if `CFGR::enable_css` is used. Returns `true` if the HSE has
+/// failed.
+/// 
+/// # Example
+/// 
+/// ```
+/// exception!(NMI, nmi);
+/// 
+/// fn nmi() {
+///     if rcc::clear_css_interrupt() {
+///         // reconfigure the peripherals using `clocks.degraded()`
+///     }
+/// }
+/// ```
+pub fn clear_css_interrupt() -> bool {
+    let rcc = unsafe { &*RCC::ptr() };
+
+    if rcc.cir.read().cssf().bit_is_set() {
+        rcc.cir.modify(|_, w| w.cssc().set_bit());
+        true
+    } else {
+        false
+    }
+}
+
+/// System clock switch status
+const SWS_HSI: u8 = 0b00;
+const SWS_HSE: u8 = 0b01;
+const SWS_PLL: u8 = 0b10;
+
+/// Returns the APB prescaler bits of the lowest division ratio of at least `ratio`
+fn ppre_bits(ratio: u32) -> u8 {
+    match ratio {
+        0 | 1 => 0b011,
+        2 => 0b100,
+        3..=4 => 0b101,
+        5..=8 => 0b110,
+        _ => 0b111,
+    }
+}
+
+/// Reason why a clock configuration cannot be applied
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ClockError {
+    /// The HSE frequency is not between 4 MHz and 16 MHz
+    HseOutOfRange,
+    /// The HSE did not become ready, the crystal may be missing
+    HseNotReady,
+    /// The requested SYSCLK is zero or higher than 72 MHz
+    SysclkOutOfRange,
+    /// The requested HCLK is zero or higher than SYSCLK
+    HclkOutOfRange,
+    /// The requested PCLK1 is zero or higher than HCLK, or PCLK1 exceeds 36 MHz
+    Pclk1OutOfRange,
+    /// The requested PCLK2 is zero or higher than HCLK
+    Pclk2OutOfRange,
+    /// The ADC clock cannot be reduced to the requested frequency
+    AdcclkOutOfRange,
+    /// The PLL does not run at 48 MHz or 72 MHz, which are required for USB
+    UsbClockImpossible,
+    /// USB is requested without `use_hse`, the HSI is not accurate enough for USB
+    UsbRequiresHse,
+    /// The LSE did not become ready, the crystal may be missing
+    LseNotReady,
+    /// Another RTC clock has been selected before, which requires a backup domain
+    /// reset to be changed
+    RtcClockLocked,
+}
+
+impl fmt::Display for ClockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClockError::HseOutOfRange => write!(f, "HSE frequency not between 4 MHz and 16 MHz"),
+            ClockError::HseNotReady => write!(f, "HSE not ready"),
+            ClockError::SysclkOutOfRange => write!(f, "SYSCLK zero or above 72 MHz"),
+            ClockError::HclkOutOfRange => write!(f, "HCLK zero or above SYSCLK"),
+            ClockError::Pclk1OutOfRange => write!(f, "PCLK1 zero, above HCLK or above 36 MHz"),
+            ClockError::Pclk2OutOfRange => write!(f, "PCLK2 zero or above HCLK"),
+            ClockError::AdcclkOutOfRange => write!(f, "ADC clock too low"),
+            ClockError::UsbClockImpossible => write!(f, "USB clock requires a PLL at 48 MHz or 72 MHz"),
+            ClockError::UsbRequiresHse => write!(f, "USB clock requires the HSE"),
+            ClockError::LseNotReady => write!(f, "LSE not ready"),
+            ClockError::RtcClockLocked => write!(f, "RTC clock selected before"),
         }
     }
 }
 
+/// Clock frequencies and prescaler settings computed by `CFGR::solve`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ClockConfig {
+    pub sysclk: u32,
+    pub hclk: u32,
+    pub pclk1: u32,
+    pub pclk2: u32,
+    pub adcclk: u32,
+    pub usbclk: Option<u32>,
+    pll: Option<Pll>,
+    hpre_bits: u8,
+    ppre1_bits: u8,
+    ppre2_bits: u8,
+    adcpre_bits: u8,
+    usbpre: bool,
+}
+
+/// PLL configuration
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Pll {
+    /// Multiplication factor (2 to 16)
+    mul: u8,
+    /// HSE divided by two as input
+    xtpre: bool,
+}
+
 /// Frozen clock frequencies
 ///
 /// The existence of this value indicates that the clock configuration can no longer be changed
@@ -290,6 +973,11 @@ pub struct Clocks {
     ppre1: u8,
     ppre2: u8,
     sysclk: Hertz,
+    adcclk: Hertz,
+    usbclk: Option<Hertz>,
+    hse: Option<Hertz>,
+    /// System clock switch status of the configuration
+    sws: u8,
 }
 
 impl Clocks {
@@ -308,20 +996,203 @@ impl Clocks {
         self.pclk2
     }
 
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn ppre1(&self) -> u8 {
         self.ppre1
     }
 
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn ppre2(&self) -> u8 {
         self.ppre2
     }
 
+    /// Returns the input frequency of the timers on the APB1 (TIM2 to TIM7)
+    /// 
+    /// The timer clock is twice the APB1 frequency if its prescaler is not 1.
+    pub fn timclk1(&self) -> Hertz {
+        if self.ppre1() == 1 { self.pclk1 } else { Hertz(self.pclk1.0 * 2) }
+    }
+
+    /// Returns the input frequency of the timers on the APB2 (TIM1 and TIM8)
+    /// 
+    /// The timer clock is twice the APB2 frequency if its prescaler is not 1.
+    pub fn timclk2(&self) -> Hertz {
+        if self.ppre2() == 1 { self.pclk2 } else { Hertz(self.pclk2.0 * 2) }
+    }
+
     /// Returns the system (core) frequency
     pub fn sysclk(&self) -> Hertz {
         self.sysclk
     }
+
+    /// Returns the frequency of the ADC clock
+    pub fn adcclk(&self) -> Hertz {
+        self.adcclk
+    }
+
+    /// Returns the frequency of the USB clock, if it has been requested using 
+    /// `CFGR::usb`
+    pub fn usbclk(&self) -> Option<Hertz> {
+        self.usbclk
+    }
+
+    /// Returns the HSE divided by 128 as a source of the RTC clock, or `None` if
+    /// the HSE is not used
+    pub fn hse_div128(&self) -> Option<HseDiv128> {
+        self.hse.map(|hse| HseDiv128(hse.0))
+    }
+
+    /// Returns the reduced frequencies after the clock security system switched the
+    /// system clock to the HSI, or `None` if the configured clocks are still in use
+    /// 
+    /// The prescalers are kept by the hardware, the USB clock is no longer available.
+    pub fn degraded(&self) -> Option<Clocks> {
+        let cfgr = unsafe { (*RCC::ptr()).cfgr.read() };
+
+        if self.sws == SWS_HSI || cfgr.sws().bits() != SWS_HSI {
+            return None;
+        }
+
+        let hclk = HSI / hpre_div(cfgr.hpre().bits());
+        let pclk1 = hclk / self.ppre1 as u32;
+        let pclk2 = hclk / self.ppre2 as u32;
+
+        Some(Clocks {
+            hclk: Hertz(hclk),
+            pclk1: Hertz(pclk1),
+            pclk2: Hertz(pclk2),
+            ppre1: self.ppre1,
+            ppre2: self.ppre2,
+            sysclk: Hertz(HSI),
+            adcclk: Hertz(pclk2 / adcpre_div(cfgr.adcpre().bits())),
+            usbclk: None,
+            hse: None,
+            sws: SWS_HSI,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::U32Ext;
+
+    #[test]
+    fn hsi_default() {
+        let config = CFGR::new().solve().unwrap();
+
+        assert_eq!(config.pll, None);
+        assert_eq!(config.sysclk, 8_000_000);
+        assert_eq!(config.hclk, 8_000_000);
+        assert_eq!(config.pclk1, 8_000_000);
+        assert_eq!(config.pclk2, 8_000_000);
+        assert_eq!(config.adcclk, 4_000_000);
+        assert_eq!(config.usbclk, None);
+    }
+
+    #[test]
+    fn hsi_pll_limit() {
+        // HSI / 2 * 16 is the highest frequency without HSE
+        let config = CFGR::new().sysclk(72_000_000.hz()).solve().unwrap();
+
+        assert_eq!(config.pll, Some(Pll { mul: 16, xtpre: false }));
+        assert_eq!(config.sysclk, 64_000_000);
+        assert_eq!(config.pclk1, 32_000_000);
+    }
+
+    #[test]
+    fn hse_72mhz_usb() {
+        let config = CFGR::new().use_hse(8_000_000.hz()).sysclk(72_000_000.hz()).usb().solve().unwrap();
+
+        assert_eq!(config.pll, Some(Pll { mul: 9, xtpre: false }));
+        assert_eq!(config.sysclk, 72_000_000);
+        assert_eq!(config.hclk, 72_000_000);
+        assert_eq!(config.pclk1, 36_000_000);
+        assert_eq!(config.pclk2, 72_000_000);
+        assert_eq!(config.adcclk, 12_000_000);
+        assert_eq!(config.usbclk, Some(48_000_000));
+        assert!(!config.usbpre);
+    }
+
+    #[test]
+    fn hse_48mhz_usb() {
+        let config = CFGR::new().use_hse(12_000_000.hz()).sysclk(48_000_000.hz()).usb().solve().unwrap();
+
+        assert_eq!(config.pll, Some(Pll { mul: 4, xtpre: false }));
+        assert_eq!(config.usbclk, Some(48_000_000));
+        assert!(config.usbpre);
+    }
+
+    #[test]
+    fn hse_divided() {
+        let config = CFGR::new().use_hse(16_000_000.hz()).sysclk(40_000_000.hz()).solve().unwrap();
+
+        assert_eq!(config.pll, Some(Pll { mul: 5, xtpre: true }));
+        assert_eq!(config.sysclk, 40_000_000);
+    }
+
+    #[test]
+    fn hse_direct() {
+        let config = CFGR::new().use_hse(12_000_000.hz()).solve().unwrap();
+
+        assert_eq!(config.pll, None);
+        assert_eq!(config.sysclk, 12_000_000);
+    }
+
+    #[test]
+    fn prescalers() {
+        let config = CFGR::new()
+            .use_hse(8_000_000.hz())
+            .sysclk(72_000_000.hz())
+            .hclk(36_000_000.hz())
+            .pclk1(9_000_000.hz())
+            .pclk2(18_000_000.hz())
+            .adcclk(3_000_000.hz())
+            .solve()
+            .unwrap();
+
+        assert_eq!(config.hclk, 36_000_000);
+        assert_eq!(config.pclk1, 9_000_000);
+        assert_eq!(config.pclk2, 18_000_000);
+        assert_eq!(config.adcclk, 3_000_000);
+    }
+
+    #[test]
+    fn prescalers_round_up() {
+        // 64 MHz / 36 MHz has to be rounded up to /2
+        let config = CFGR::new().use_hse(8_000_000.hz()).sysclk(64_000_000.hz()).pclk1(36_000_000.hz()).solve().unwrap();
+
+        assert_eq!(config.pclk1, 32_000_000);
+
+        let config = CFGR::new()
+            .use_hse(8_000_000.hz())
+            .sysclk(72_000_000.hz())
+            .hclk(15_000_000.hz())
+            .pclk2(5_000_000.hz())
+            .solve()
+            .unwrap();
+
+        assert_eq!(config.hclk, 9_000_000);
+        assert_eq!(config.pclk2, 4_500_000);
+    }
+
+    #[test]
+    fn hclk_64() {
+        let config = CFGR::new().hclk(125_000.hz()).solve().unwrap();
+
+        assert_eq!(config.hclk, 125_000);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(CFGR::new().use_hse(25_000_000.hz()).solve(), Err(ClockError::HseOutOfRange));
+        assert_eq!(CFGR::new().sysclk(80_000_000.hz()).solve(), Err(ClockError::SysclkOutOfRange));
+        assert_eq!(CFGR::new().hclk(16_000_000.hz()).solve(), Err(ClockError::HclkOutOfRange));
+        assert_eq!(CFGR::new().hclk(0.hz()).solve(), Err(ClockError::HclkOutOfRange));
+        assert_eq!(CFGR::new().pclk1(16_000_000.hz()).solve(), Err(ClockError::Pclk1OutOfRange));
+        assert_eq!(CFGR::new().pclk2(16_000_000.hz()).solve(), Err(ClockError::Pclk2OutOfRange));
+        assert_eq!(CFGR::new().use_hse(8_000_000.hz()).sysclk(64_000_000.hz()).usb().solve(), 
+            Err(ClockError::UsbClockImpossible));
+        assert_eq!(CFGR::new().use_hse(8_000_000.hz()).usb().solve(), Err(ClockError::UsbClockImpossible));
+        assert_eq!(CFGR::new().sysclk(48_000_000.hz()).usb().solve(), Err(ClockError::UsbRequiresHse));
+        assert_eq!(CFGR::new().adcclk(100_000.hz()).solve(), Err(ClockError::AdcclkOutOfRange));
+    }
 }
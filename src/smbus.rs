@@ -0,0 +1,279 @@
+//! System Management Bus
+//! 
+//! Implements the SMBus host protocols on top of an I2C master. The peripheral is
+//! switched to the SMBus host mode, the packet error checking (PEC) byte is 
+//! generated and checked by the hardware if enabled.
+//! 
+//! Devices signal an alert by pulling the SMBALERT line low, which is PB5 for I2C1
+//! and PB12 for I2C2. The alerting device is determined using the alert response
+//! address.
+//! 
+//! # Example
+//! 
+//! ```
+//! let i2c = I2c::new(dp.I2C1, ports, rcc.peripherals.i2c1.enable(), 100.khz(), clocks, I2cDutyCycle::Ratio1to1);
+//! let mut smbus = SmBus::new(i2c, true);
+//! 
+//! let pb5 = pinsb.5.set_output_2MHz().set_alt_output_open_drain();
+//! smbus.enable_alert(SmBus::alert_port_normal(pb5));
+//! 
+//! let voltage = smbus.read_word(0x0B, 0x09)?;
+//! 
+//! let mut name = [0u8; 32];
+//! let len = smbus.block_read(0x0B, 0x21, &mut name)?;
+//! 
+//! if smbus.has_alert() {
+//!     let device = smbus.alert_response()?;
+//! }
+//! ```
+//! 
+//! After an error the stop signal is generated and the error flags are cleared, 
+//! so a device that does not acknowledge does not block the bus:
+//! 
+//! ```
+//! match smbus.read_byte(0x48, 0x00) {
+//!     Err(Error::AF) => {
+//!         // no device at 0x48, the bus is free for the next transfer
+//!     },
+//!     res => { let temperature = res?; },
+//! }
+//! 
+//! let voltage = smbus.read_word(0x0B, 0x09)?;
+//! ```
+
+#[allow(unused_imports)]
+use common;
+
+use core::any::Any;
+use core::marker::PhantomData;
+
+use stm32f103xx::{GPIOB, I2C1, I2C2};
+
+use afio::{IsRemapped, Remapped, NotRemapped};
+use gpio::{GpioPin, Pin5, Pin12, PinCnf3, PinOutput, PinMode};
+use i2c::{Address, Error, I2c, I2C, Master};
+
+/// Alert response address, read by the host to determine the alerting device
+const ALERT_RESPONSE_ADDRESS: u8 = 0x0C;
+
+/// Maximum number of bytes of a block transfer
+pub const BLOCK_SIZE: usize = 32;
+
+/// SMBALERT pin of an I2C module
+pub struct SmBusAlert<S> where S: Any + I2C {
+    i2c: PhantomData<S>,
+}
+
+pub struct SmBus<S, R> where S: Any + I2C, R: IsRemapped {
+    i2c: I2c<S, R, Master>,
+    alert: Option<SmBusAlert<S>>,
+    pec: bool,
+}
+
+impl<S, R> SmBus<S, R>
+where
+    S: Any + I2C,
+    R: IsRemapped
+{
+    /// Switches the I2C module to the SMBus host mode
+    /// 
+    /// # Arguments
+    /// - `i2c`: The I2C master.
+    /// - `pec`: Whether a PEC byte is appended to every transfer, except for the
+    ///   quick command.
+    pub fn new(mut i2c: I2c<S, R, Master>, pec: bool) -> Self {
+        i2c.set_smbus(true, pec, false);
+
+        SmBus {
+            i2c,
+            alert: None,
+            pec,
+        }
+    }
+
+    /// Switches back to the I2C mode and returns the I2C master and the SMBALERT pin
+    pub fn free(mut self) -> (I2c<S, R, Master>, Option<SmBusAlert<S>>) {
+        self.i2c.set_smbus(false, false, false);
+        (self.i2c, self.alert)
+    }
+
+    /// Enables the detection of alerts on the SMBALERT pin
+    /// 
+    /// The SMBALERT event raises the I2C error interrupt if enabled using `listen`.
+    pub fn enable_alert(&mut self, alert: SmBusAlert<S>) {
+        self.alert = Some(alert);
+        self.i2c.set_smbus(true, self.pec, true);
+        self.i2c.take_alert();
+    }
+
+    /// Returns `true` if a device signaled an alert, clearing the event
+    pub fn has_alert(&mut self) -> bool {
+        self.i2c.take_alert()
+    }
+
+    /// Reads the alert response address and returns the 7-bit address of the
+    /// alerting device. If several devices are alerting, the one with the lowest
+    /// address responds.
+    pub fn alert_response(&mut self) -> Result<u8, Error> {
+        self.receive_byte(ALERT_RESPONSE_ADDRESS).map(|addr| addr >> 1)
+    }
+
+    /// Sends the address with the read / write bit only, without any data
+    /// 
+    /// # Remarks
+    /// 
+    /// If `read` is set, the hardware cannot generate the stop signal before the
+    /// first data byte. The byte is received and ignored.
+    pub fn quick_command(&mut self, addr: u8, read: bool) -> Result<(), Error> {
+        let res = self.try_quick_command(addr, read);
+        self.i2c.release(res)
+    }
+
+    fn try_quick_command(&mut self, addr: u8, read: bool) -> Result<(), Error> {
+        self.i2c.address(Address::Seven(addr), read)?;
+
+        if read {
+            let mut dummy = [0u8];
+            self.i2c.receive(&mut dummy, false)
+        } else {
+            // clear ADDR
+            self.i2c.read_sr2()?;
+            self.stop()
+        }
+    }
+
+    /// Sends a single byte without a command code
+    pub fn send_byte(&mut self, addr: u8, byte: u8) -> Result<(), Error> {
+        self.write(addr, &[byte])
+    }
+
+    /// Receives a single byte without a command code
+    pub fn receive_byte(&mut self, addr: u8) -> Result<u8, Error> {
+        let mut buffer = [0u8; 2];
+        self.read(addr, &[], &mut buffer, 1)?;
+        Ok(buffer[0])
+    }
+
+    /// Writes a byte to the given command code
+    pub fn write_byte(&mut self, addr: u8, command: u8, byte: u8) -> Result<(), Error> {
+        self.write(addr, &[command, byte])
+    }
+
+    /// Reads a byte from the given command code
+    pub fn read_byte(&mut self, addr: u8, command: u8) -> Result<u8, Error> {
+        let mut buffer = [0u8; 2];
+        self.read(addr, &[command], &mut buffer, 1)?;
+        Ok(buffer[0])
+    }
+
+    /// Writes a word to the given command code, the low byte is sent first
+    pub fn write_word(&mut self, addr: u8, command: u8, word: u16) -> Result<(), Error> {
+        self.write(addr, &[command, word as u8, (word >> 8) as u8])
+    }
+
+    /// Reads a word from the given command code, the low byte is received first
+    pub fn read_word(&mut self, addr: u8, command: u8) -> Result<u16, Error> {
+        let mut buffer = [0u8; 3];
+        self.read(addr, &[command], &mut buffer, 2)?;
+        Ok(buffer[0] as u16 | (buffer[1] as u16) << 8)
+    }
+
+    /// Writes a block of up to 32 bytes to the given command code, preceded by the
+    /// byte count
+    pub fn block_write(&mut self, addr: u8, command: u8, bytes: &[u8]) -> Result<(), Error> {
+        assert!(!bytes.is_empty() && bytes.len() <= BLOCK_SIZE);
+
+        let mut buffer = [0u8; BLOCK_SIZE + 2];
+        buffer[0] = command;
+        buffer[1] = bytes.len() as u8;
+        buffer[2..bytes.len() + 2].copy_from_slice(bytes);
+
+        self.write(addr, &buffer[..bytes.len() + 2])
+    }
+
+    /// Reads a block from the given command code and returns the number of bytes
+    /// received. Returns `Error::InvalidLength` if the count sent by the device is 
+    /// zero or exceeds the buffer.
+    pub fn block_read(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<usize, Error> {
+        let res = self.try_block_read(addr, command, buffer);
+        self.i2c.release(res)
+    }
+
+    fn try_block_read(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<usize, Error> {
+        let addr = Address::Seven(addr);
+        let pec = self.pec;
+
+        self.i2c.address(addr, false)?;
+        self.i2c.transmit(&[command], false)?;
+
+        self.i2c.restart_read(addr)?;
+        let len = BLOCK_SIZE.min(buffer.len());
+        self.i2c.receive_block(&mut buffer[..len], pec)
+    }
+
+    /// Writes the bytes followed by the PEC and generates the stop signal
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        let res = self.try_write(addr, bytes);
+        self.i2c.release(res)
+    }
+
+    fn try_write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        let pec = self.pec;
+
+        self.i2c.address(Address::Seven(addr), false)?;
+        self.i2c.transmit(bytes, pec)?;
+        self.stop()
+    }
+
+    /// Writes the command code, if any, and reads `len` bytes into the buffer 
+    /// followed by the PEC. The buffer has to provide space for the PEC byte.
+    fn read(&mut self, addr: u8, command: &[u8], buffer: &mut [u8], len: usize) -> Result<(), Error> {
+        let res = self.try_read(addr, command, buffer, len);
+        self.i2c.release(res)
+    }
+
+    fn try_read(&mut self, addr: u8, command: &[u8], buffer: &mut [u8], len: usize) -> Result<(), Error> {
+        let addr = Address::Seven(addr);
+        let pec = self.pec;
+
+        if !command.is_empty() {
+            self.i2c.address(addr, false)?;
+            self.i2c.transmit(command, false)?;
+            self.i2c.restart_read(addr)?;
+        } else {
+            self.i2c.address(addr, true)?;
+        }
+
+        // the PEC is received into the byte following the data
+        self.i2c.receive(&mut buffer[..len + pec as usize], pec)
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        self.i2c.stop()?;
+        self.i2c.wait(I2c::<S, R, Master>::stop_complete)
+    }
+}
+
+impl SmBus<I2C1, NotRemapped> {
+    #[inline(always)]
+    pub fn alert_port_normal<M>(_pb5: GpioPin<GPIOB, Pin5, M, PinCnf3>) -> SmBusAlert<I2C1> 
+        where M: PinOutput + PinMode {
+        SmBusAlert { i2c: PhantomData }
+    }
+}
+
+impl SmBus<I2C1, Remapped> {
+    #[inline(always)]
+    pub fn alert_port_remapped<M>(_pb5: GpioPin<GPIOB, Pin5, M, PinCnf3>) -> SmBusAlert<I2C1> 
+        where M: PinOutput + PinMode {
+        SmBusAlert { i2c: PhantomData }
+    }
+}
+
+impl SmBus<I2C2, NotRemapped> {
+    #[inline(always)]
+    pub fn alert_port_i2c2<M>(_pb12: GpioPin<GPIOB, Pin12, M, PinCnf3>) -> SmBusAlert<I2C2> 
+        where M: PinOutput + PinMode {
+        SmBusAlert { i2c: PhantomData }
+    }
+}
@@ -13,10 +13,70 @@
 //! let _spi1 = Spi::new(
 //!     &dp.SPI1, 
 //!     Spi::ports_normal(pa4, pa5, pa6, pa7, afio_periph.spi1.set_not_remapped()),
-//!     rcc.peripherals.sp1.enable(),
+//!     rcc.peripherals.spi1.enable(),
+//!     1.mhz().into(),
+//!     clocks,
 //! );
 //! ```
 //! 
+//! # Pins
+//! 
+//! | Instance         | NSS  | SCK  | MISO | MOSI |
+//! |------------------|------|------|------|------|
+//! | SPI1             | PA4  | PA5  | PA6  | PA7  |
+//! | SPI1 (remapped)  | PA15 | PB3  | PB4  | PB5  |
+//! | SPI2             | PB12 | PB13 | PB14 | PB15 |
+//! 
+//! The remapped SPI1 pins are shared with JTAG, which has to be released using
+//! `afio_periph.swj.release_jtag()` first. SPI2 cannot be remapped.
+//! 
+//! # Half duplex modes
+//! 
+//! In bidirectional (3-wire) mode only SCK and MOSI are used, with MOSI acting as
+//! the single data line. In receive-only mode only SCK and MISO are used. Both 
+//! modes use software slave management, the slave has to be selected using a GPIO pin.
+//! 
+//! ```
+//! let pa5 = pinsa.5.set_output_10MHz().set_alt_output_push_pull();
+//! let pa7 = pinsa.7.set_output_10MHz().set_alt_output_push_pull();
+//!
+//! let mut spi1 = Spi::new_bidirectional(
+//!     &dp.SPI1, 
+//!     Spi::ports_bidirectional(pa5, pa7, afio_periph.spi1.set_not_remapped()),
+//!     rcc.peripherals.spi1.enable(),
+//!     hal::spi::MODE_0,
+//!     1.mhz().into(),
+//!     clocks,
+//! );
+//! 
+//! let mut id = [0; 3];
+//! spi1.write(&[0x04]).unwrap();
+//! spi1.read(&mut id).unwrap();
+//! ```
+//! 
+//! # Slave mode
+//! 
+//! In slave mode SCK, MOSI and NSS are inputs and only MISO is driven. NSS can
+//! either be taken from the pin (hardware NSS) or be controlled in software.
+//! 
+//! ```
+//! let pa4 = pinsa.4.set_input().set_floating_input();
+//! let pa5 = pinsa.5.set_input().set_floating_input();
+//! let pa6 = pinsa.6.set_output_50MHz().set_alt_output_push_pull();
+//! let pa7 = pinsa.7.set_input().set_floating_input();
+//!
+//! let mut spi1 = Spi::new_slave(
+//!     &dp.SPI1, 
+//!     Spi::slave_ports_normal(pa4, pa5, pa6, pa7, afio_periph.spi1.set_not_remapped()),
+//!     rcc.peripherals.spi1.enable(),
+//!     hal::spi::MODE_0,
+//! );
+//! 
+//! spi1.preload(0xFF).unwrap();
+//! spi1.listen(false, true);
+//! spi1.enable();
+//! ```
+//! 
 
 #[allow(unused_imports)]
 use common;
@@ -25,6 +85,9 @@ use core::any::Any;
 use core::ops::Deref;
 use core::ptr;
 use core::marker::PhantomData;
+use core::sync::atomic::{self, Ordering};
+
+use cortex_m::asm;
 
 use nb;
 use hal;
@@ -32,23 +95,60 @@ use rcc;
 
 use time::Hertz;
 use rcc::{Clocks, RccPeripheral};
-use gpio::{Input, GpioPin, Pin4, Pin5, Pin6, Pin7, Pin12, Pin13, Pin14, Pin15, PinMode, PinCnf1, PinCnf2};
-use afio::{AfioPeripheral, IsRemapped, Remapped, NotRemapped};
-use stm32f103xx::{GPIOA, GPIOB, SPI1, SPI2, spi1, gpioa};
+use gpio::{Input, GpioPin, Pin3, Pin4, Pin5, Pin6, Pin7, Pin12, Pin13, Pin14, Pin15, PinMode, PinOutput, PinCnf1, PinCnf2};
+use afio::{AfioPeripheral, IsRemapped, Remapped, NotRemapped, Swj, JtagReleased};
+use dma::{self, DmaChannel, DMA, Channels, ChannelNr, C2, C3, C4, C5};
+use stm32f103xx::{GPIOA, GPIOB, SPI1, SPI2, DMA1, spi1, gpioa};
+#[cfg(feature = "high-density")]
+use dma::C1;
+#[cfg(feature = "high-density")]
+use stm32f103xx::{SPI3, DMA2};
+
+type_states!(SpiMode, (Master, Slave, Bidirectional, ReceiveOnly));
+type_group!(FullDuplexMode, (Master, Slave));
 
 
 /// SPI instance that can be used with the `Spi` abstraction
 pub unsafe trait SPI: Deref<Target = spi1::RegisterBlock> {
     /// GPIO block associated to this SPI instance
     type GPIO: Deref<Target = gpioa::RegisterBlock>;
+    /// DMA channel serving the receive buffer
+    type DmaRx: Channels + ChannelNr;
+    /// DMA channel serving the transmit buffer
+    type DmaTx: Channels + ChannelNr;
+    /// DMA controller providing `DmaRx` and `DmaTx`
+    type Dma: DMA;
+
+    /// Returns the frequency of the bus this SPI instance is connected to
+    fn clock(clocks: &Clocks) -> Hertz;
 }
 
 unsafe impl SPI for SPI1 {
     type GPIO = GPIOA;
+    type DmaRx = C2;
+    type DmaTx = C3;
+    type Dma = DMA1;
+
+    fn clock(clocks: &Clocks) -> Hertz { clocks.pclk2() }
 }
 
 unsafe impl SPI for SPI2 {
     type GPIO = GPIOB;
+    type DmaRx = C4;
+    type DmaTx = C5;
+    type Dma = DMA1;
+
+    fn clock(clocks: &Clocks) -> Hertz { clocks.pclk1() }
+}
+
+#[cfg(feature = "high-density")]
+unsafe impl SPI for SPI3 {
+    type GPIO = GPIOB;
+    type DmaRx = C1;
+    type DmaTx = C2;
+    type Dma = DMA2;
+
+    fn clock(clocks: &Clocks) -> Hertz { clocks.pclk1() }
 }
 
 #[derive(Copy, Clone)]
@@ -78,14 +178,38 @@ pub enum Error {
     BusError,
     /// CRC Error
     CrcError,
+    /// DMA Transfer Error
+    /// 
+    /// The DMA controller could not access the buffer.
+    TransferError,
 }
 
 
-pub struct SpiBusPorts<S, R>(PhantomData<(S, R)>)
-where S: Any + SPI, R: IsRemapped;
+/// Selects how the slave select signal of a slave is driven
+pub trait SlaveSelect {
+    /// Returns `true` if the NSS signal is managed in software (`SSM`)
+    fn software() -> bool;
+}
+
+/// NSS is read from the NSS pin
+pub struct HardwareNss {}
 
-pub struct Spi<'a, S, R>(pub &'a S, PhantomData<R>)
-where S: Any + SPI, R: IsRemapped;
+/// NSS is ignored and the slave is selected using the `SSI` bit
+pub struct SoftwareNss {}
+
+impl SlaveSelect for HardwareNss { #[inline(always)] fn software() -> bool { false } }
+impl SlaveSelect for SoftwareNss { #[inline(always)] fn software() -> bool { true } }
+
+pub struct SpiBusPorts<S, R, M = Master>(PhantomData<(S, R, M)>)
+where S: Any + SPI, R: IsRemapped, M: SpiMode;
+
+pub struct SpiSlavePorts<S, R, N>(PhantomData<(S, R, N)>)
+where S: Any + SPI, R: IsRemapped, N: SlaveSelect;
+
+/// The third field is the number of core clock cycles per bus clock cycle, which
+/// is required to time the end of a reception in the half duplex modes.
+pub struct Spi<'a, S, R, M = Master>(pub &'a S, PhantomData<(R, M)>, u32)
+where S: Any + SPI, R: IsRemapped, M: SpiMode;
 
 impl<'a> Spi<'a, SPI1, NotRemapped> {
     pub fn ports_normal<M>(
@@ -94,24 +218,159 @@ impl<'a> Spi<'a, SPI1, NotRemapped> {
         _pa6 : GpioPin<GPIOA, Pin6, Input, PinCnf1>,
         _pa7 : GpioPin<GPIOA, Pin7, M, PinCnf2>,
         _afio_spi : AfioPeripheral<SPI1, NotRemapped>) -> SpiBusPorts<SPI1, NotRemapped> 
-        where M : PinMode {
+        where M : PinOutput + PinMode {
             SpiBusPorts(PhantomData)
         }
 }
 
-impl<'a> Spi<'a, SPI2, Remapped> {
+impl<'a> Spi<'a, SPI1, Remapped> {
     pub fn ports_remapped<M>(
-        _pa4 : GpioPin<GPIOA, Pin4, M, PinCnf2>, 
+        _pa15 : GpioPin<GPIOA, Pin15, M, PinCnf2>, 
+        _pb3 : GpioPin<GPIOB, Pin3, M, PinCnf2>,
+        _pb4 : GpioPin<GPIOB, Pin4, Input, PinCnf1>,
+        _pb5 : GpioPin<GPIOB, Pin5, M, PinCnf2>,
+        _afio_spi : AfioPeripheral<SPI1, Remapped>,
+        _swj : &Swj<JtagReleased>) -> SpiBusPorts<SPI1, Remapped> 
+        where M : PinOutput + PinMode {
+            SpiBusPorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI2, NotRemapped> {
+    pub fn ports_spi2<M>(
         _pb12 : GpioPin<GPIOB, Pin12, M, PinCnf2>, 
         _pb13 : GpioPin<GPIOB, Pin13, M, PinCnf2>,
         _pb14 : GpioPin<GPIOB, Pin14, Input, PinCnf1>,
         _pb15 : GpioPin<GPIOB, Pin15, M, PinCnf2>) -> SpiBusPorts<SPI2, NotRemapped> 
-        where M : PinMode {
-            SpiBusPorts(PhantomData)        
+        where M : PinOutput + PinMode {
+            SpiBusPorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI1, NotRemapped, Bidirectional> {
+    pub fn ports_bidirectional<M>(
+        _pa5 : GpioPin<GPIOA, Pin5, M, PinCnf2>,
+        _pa7 : GpioPin<GPIOA, Pin7, M, PinCnf2>,
+        _afio_spi : AfioPeripheral<SPI1, NotRemapped>) -> SpiBusPorts<SPI1, NotRemapped, Bidirectional> 
+        where M : PinOutput + PinMode {
+            SpiBusPorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI1, Remapped, Bidirectional> {
+    pub fn ports_bidirectional_remapped<M>(
+        _pb3 : GpioPin<GPIOB, Pin3, M, PinCnf2>,
+        _pb5 : GpioPin<GPIOB, Pin5, M, PinCnf2>,
+        _afio_spi : AfioPeripheral<SPI1, Remapped>,
+        _swj : &Swj<JtagReleased>) -> SpiBusPorts<SPI1, Remapped, Bidirectional> 
+        where M : PinOutput + PinMode {
+            SpiBusPorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI2, NotRemapped, Bidirectional> {
+    pub fn ports_bidirectional_spi2<M>(
+        _pb13 : GpioPin<GPIOB, Pin13, M, PinCnf2>,
+        _pb15 : GpioPin<GPIOB, Pin15, M, PinCnf2>) -> SpiBusPorts<SPI2, NotRemapped, Bidirectional> 
+        where M : PinOutput + PinMode {
+            SpiBusPorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI1, NotRemapped, ReceiveOnly> {
+    pub fn ports_receive_only<M>(
+        _pa5 : GpioPin<GPIOA, Pin5, M, PinCnf2>,
+        _pa6 : GpioPin<GPIOA, Pin6, Input, PinCnf1>,
+        _afio_spi : AfioPeripheral<SPI1, NotRemapped>) -> SpiBusPorts<SPI1, NotRemapped, ReceiveOnly> 
+        where M : PinOutput + PinMode {
+            SpiBusPorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI1, Remapped, ReceiveOnly> {
+    pub fn ports_receive_only_remapped<M>(
+        _pb3 : GpioPin<GPIOB, Pin3, M, PinCnf2>,
+        _pb4 : GpioPin<GPIOB, Pin4, Input, PinCnf1>,
+        _afio_spi : AfioPeripheral<SPI1, Remapped>,
+        _swj : &Swj<JtagReleased>) -> SpiBusPorts<SPI1, Remapped, ReceiveOnly> 
+        where M : PinOutput + PinMode {
+            SpiBusPorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI2, NotRemapped, ReceiveOnly> {
+    pub fn ports_receive_only_spi2<M>(
+        _pb13 : GpioPin<GPIOB, Pin13, M, PinCnf2>,
+        _pb14 : GpioPin<GPIOB, Pin14, Input, PinCnf1>) -> SpiBusPorts<SPI2, NotRemapped, ReceiveOnly> 
+        where M : PinOutput + PinMode {
+            SpiBusPorts(PhantomData)
         }
 }
 
-impl<'a, S, R> Spi<'a, S, R>
+impl<'a> Spi<'a, SPI1, NotRemapped, Slave> {
+    pub fn slave_ports_normal<M>(
+        _pa4 : GpioPin<GPIOA, Pin4, Input, PinCnf1>, 
+        _pa5 : GpioPin<GPIOA, Pin5, Input, PinCnf1>,
+        _pa6 : GpioPin<GPIOA, Pin6, M, PinCnf2>,
+        _pa7 : GpioPin<GPIOA, Pin7, Input, PinCnf1>,
+        _afio_spi : AfioPeripheral<SPI1, NotRemapped>) -> SpiSlavePorts<SPI1, NotRemapped, HardwareNss> 
+        where M : PinOutput + PinMode {
+            SpiSlavePorts(PhantomData)
+        }
+
+    pub fn slave_ports_normal_software_nss<M>(
+        _pa5 : GpioPin<GPIOA, Pin5, Input, PinCnf1>,
+        _pa6 : GpioPin<GPIOA, Pin6, M, PinCnf2>,
+        _pa7 : GpioPin<GPIOA, Pin7, Input, PinCnf1>,
+        _afio_spi : AfioPeripheral<SPI1, NotRemapped>) -> SpiSlavePorts<SPI1, NotRemapped, SoftwareNss> 
+        where M : PinOutput + PinMode {
+            SpiSlavePorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI1, Remapped, Slave> {
+    pub fn slave_ports_remapped<M>(
+        _pa15 : GpioPin<GPIOA, Pin15, Input, PinCnf1>, 
+        _pb3 : GpioPin<GPIOB, Pin3, Input, PinCnf1>,
+        _pb4 : GpioPin<GPIOB, Pin4, M, PinCnf2>,
+        _pb5 : GpioPin<GPIOB, Pin5, Input, PinCnf1>,
+        _afio_spi : AfioPeripheral<SPI1, Remapped>,
+        _swj : &Swj<JtagReleased>) -> SpiSlavePorts<SPI1, Remapped, HardwareNss> 
+        where M : PinOutput + PinMode {
+            SpiSlavePorts(PhantomData)
+        }
+
+    pub fn slave_ports_remapped_software_nss<M>(
+        _pb3 : GpioPin<GPIOB, Pin3, Input, PinCnf1>,
+        _pb4 : GpioPin<GPIOB, Pin4, M, PinCnf2>,
+        _pb5 : GpioPin<GPIOB, Pin5, Input, PinCnf1>,
+        _afio_spi : AfioPeripheral<SPI1, Remapped>,
+        _swj : &Swj<JtagReleased>) -> SpiSlavePorts<SPI1, Remapped, SoftwareNss> 
+        where M : PinOutput + PinMode {
+            SpiSlavePorts(PhantomData)
+        }
+}
+
+impl<'a> Spi<'a, SPI2, NotRemapped, Slave> {
+    pub fn slave_ports_spi2<M>(
+        _pb12 : GpioPin<GPIOB, Pin12, Input, PinCnf1>, 
+        _pb13 : GpioPin<GPIOB, Pin13, Input, PinCnf1>,
+        _pb14 : GpioPin<GPIOB, Pin14, M, PinCnf2>,
+        _pb15 : GpioPin<GPIOB, Pin15, Input, PinCnf1>) -> SpiSlavePorts<SPI2, NotRemapped, HardwareNss> 
+        where M : PinOutput + PinMode {
+            SpiSlavePorts(PhantomData)
+        }
+
+    pub fn slave_ports_spi2_software_nss<M>(
+        _pb13 : GpioPin<GPIOB, Pin13, Input, PinCnf1>,
+        _pb14 : GpioPin<GPIOB, Pin14, M, PinCnf2>,
+        _pb15 : GpioPin<GPIOB, Pin15, Input, PinCnf1>) -> SpiSlavePorts<SPI2, NotRemapped, SoftwareNss> 
+        where M : PinOutput + PinMode {
+            SpiSlavePorts(PhantomData)
+        }
+}
+
+impl<'a, S, R> Spi<'a, S, R, Master>
 where S: Any + SPI, R: IsRemapped,
 {
     pub fn new(spi: &'a S, _ports: SpiBusPorts<S, R>, _rcc_periph: RccPeripheral<S, rcc::Enabled>, freq: Hertz, clocks: Clocks) -> Self {
@@ -119,17 +378,7 @@ where S: Any + SPI, R: IsRemapped,
         // enable slave select
         spi.cr2.modify(|_, w| { w.ssoe().set_bit() });
 
-        let br = match clocks.pclk2().0 / freq.0 {
-            0 => unreachable!(),
-            1...2 => 0b000,
-            3...5 => 0b001,
-            6...11 => 0b010,
-            12...23 => 0b011,
-            24...47 => 0b100,
-            48...95 => 0b101,
-            96...191 => 0b110,
-            _ => 0b111,
-        };
+        let br = baud_rate_bits(S::clock(&clocks), freq);
 
         spi.cr1.write(|w| {
             w.cpha()
@@ -152,9 +401,332 @@ where S: Any + SPI, R: IsRemapped,
             .clear_bit()
         });
 
-        Spi(spi, PhantomData)
+        Spi(spi, PhantomData, apb_ratio::<S>(&clocks))
     }
 
+    /// Changes the clock polarity, phase and frequency of the bus
+    /// 
+    /// The bus is disabled while the configuration is changed and enabled again 
+    /// afterwards.
+    pub fn reconfigure<F>(&mut self, mode: hal::spi::Mode, freq: F, clocks: Clocks)
+        where F: Into<Hertz> {
+        let br = baud_rate_bits(S::clock(&clocks), freq.into());
+
+        self.configure(
+            mode.polarity == hal::spi::Polarity::IdleHigh,
+            mode.phase == hal::spi::Phase::CaptureOnSecondTransition,
+            br);
+    }
+
+    pub(crate) fn configure(&mut self, cpol: bool, cpha: bool, br: u8) {
+        self.wait_idle();
+        self.disable();
+
+        self.0.cr1.modify(|_, w| {
+            w.cpha()
+            .bit(cpha)
+            .cpol()
+            .bit(cpol)
+            .br()
+            .bits(br)
+        });
+
+        self.enable();
+    }
+}
+
+/// Returns the number of core clock cycles per cycle of the bus clock of `S`
+fn apb_ratio<S>(clocks: &Clocks) -> u32 
+    where S: SPI {
+    clocks.hclk().0 / S::clock(clocks).0
+}
+
+/// Returns the `BR` bits which divide `pclk` down to at most `freq`
+/// 
+/// Frequencies above `pclk / 2` are limited to `pclk / 2`.
+pub(crate) fn baud_rate_bits(pclk: Hertz, freq: Hertz) -> u8 {
+    match pclk.0 / freq.0 {
+        0..=2 => 0b000,
+        3..=5 => 0b001,
+        6..=11 => 0b010,
+        12..=23 => 0b011,
+        24..=47 => 0b100,
+        48..=95 => 0b101,
+        96..=191 => 0b110,
+        _ => 0b111,
+    }
+}
+
+impl<'a, S, R> Spi<'a, S, R, Slave>
+where S: Any + SPI, R: IsRemapped,
+{
+    /// Initializes the SPI module as a slave
+    /// 
+    /// The clock is provided by the master, so only the clock polarity and phase
+    /// have to be configured. The bus has to be enabled using `enable` once the
+    /// first reply byte has been loaded using `preload`.
+    pub fn new_slave<N>(spi: &'a S, _ports: SpiSlavePorts<S, R, N>, _rcc_periph: RccPeripheral<S, rcc::Enabled>, mode: hal::spi::Mode) -> Self 
+        where N: SlaveSelect {
+
+        // NSS is an input in slave mode
+        spi.cr2.modify(|_, w| { w.ssoe().clear_bit() });
+
+        spi.cr1.write(|w| {
+            w.cpha()
+            .bit(mode.phase == hal::spi::Phase::CaptureOnSecondTransition)
+            .cpol()
+            .bit(mode.polarity == hal::spi::Polarity::IdleHigh)
+            .mstr()
+            .clear_bit()
+            .lsbfirst()
+            .clear_bit()
+            .ssm()
+            .bit(N::software())
+            .ssi()
+            .clear_bit()
+            .rxonly()
+            .clear_bit()
+            .dff()
+            .clear_bit()
+            .bidimode()
+            .clear_bit()
+        });
+
+        Spi(spi, PhantomData, 1)
+    }
+
+    /// Selects or deselects the slave when using software slave management.
+    /// 
+    /// Has no effect if the slave select is read from the NSS pin.
+    pub fn set_selected(&mut self, selected: bool) {
+        self.0.cr1.modify(|_, w| w.ssi().bit(!selected))
+    }
+
+    /// Loads the byte that is shifted out during the next frame clocked by the master.
+    /// 
+    /// Returns `Err(nb::Error::WouldBlock)` if the previous byte has not yet been
+    /// moved to the shift register.
+    pub fn preload(&mut self, byte: u8) -> nb::Result<(), Error> {
+        let sr = self.0.sr.read();
+
+        Err(if sr.ovr().bit_is_set() {
+            nb::Error::Other(Error::Overrun)
+        } else if sr.txe().bit_is_set() {
+            unsafe { common::write_register(&self.0.dr, byte) }
+            return Ok(());
+        } else {
+            nb::Error::WouldBlock
+        })
+    }
+
+    /// Reads a received byte and immediately loads the reply returned by `reply`.
+    /// 
+    /// Intended to be called from the `RXNE` interrupt so that the reply is ready
+    /// before the master starts clocking the next frame. Returns the received byte.
+    /// 
+    /// Returns `Err(nb::Error::WouldBlock)` until a byte has been received and the
+    /// previous reply has been moved to the shift register.
+    pub fn exchange<F>(&mut self, reply: F) -> nb::Result<u8, Error>
+        where F: FnOnce(u8) -> u8 {
+        let sr = self.0.sr.read();
+
+        Err(if sr.ovr().bit_is_set() {
+            nb::Error::Other(Error::Overrun)
+        } else if sr.rxne().bit_is_set() && sr.txe().bit_is_set() {
+            let byte = unsafe { ptr::read_volatile(&self.0.dr as *const _ as *const u8) };
+            unsafe { common::write_register(&self.0.dr, reply(byte)) }
+            return Ok(byte);
+        } else {
+            nb::Error::WouldBlock
+        })
+    }
+}
+
+impl<'a, S, R> Spi<'a, S, R, Bidirectional>
+where S: Any + SPI, R: IsRemapped,
+{
+    /// Initializes the SPI module as a master using a single bidirectional data line
+    pub fn new_bidirectional(spi: &'a S, _ports: SpiBusPorts<S, R, Bidirectional>, _rcc_periph: RccPeripheral<S, rcc::Enabled>, 
+        mode: hal::spi::Mode, freq: Hertz, clocks: Clocks) -> Self {
+
+        spi.cr2.modify(|_, w| { w.ssoe().clear_bit() });
+
+        let br = baud_rate_bits(S::clock(&clocks), freq);
+
+        // BIDIOE has to be set, otherwise the clock starts running as soon as the bus is enabled
+        spi.cr1.write(|w| {
+            w.cpha()
+            .bit(mode.phase == hal::spi::Phase::CaptureOnSecondTransition)
+            .cpol()
+            .bit(mode.polarity == hal::spi::Polarity::IdleHigh)
+            .mstr()
+            .set_bit()
+            .br()
+            .bits(br)
+            .lsbfirst()
+            .clear_bit()
+            .ssm()
+            .set_bit()
+            .ssi()
+            .set_bit()
+            .rxonly()
+            .clear_bit()
+            .dff()
+            .clear_bit()
+            .bidimode()
+            .set_bit()
+            .bidioe()
+            .set_bit()
+        });
+
+        Spi(spi, PhantomData, apb_ratio::<S>(&clocks))
+    }
+
+    /// Drives the data line and writes all bytes
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.0.cr1.modify(|_, w| w.bidioe().set_bit());
+        self.enable();
+
+        for byte in bytes {
+            while self.0.sr.read().txe().bit_is_clear() {}
+            unsafe { common::write_register(&self.0.dr, *byte) }
+        }
+
+        while self.0.sr.read().txe().bit_is_clear() {}
+        self.wait_idle();
+
+        Ok(())
+    }
+
+    /// Releases the data line and fills the buffer with received bytes
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        // the clock starts as soon as the output is disabled, so the bus has to be
+        // disabled while switching the direction
+        self.wait_idle();
+        self.disable();
+        self.0.cr1.modify(|_, w| w.bidioe().clear_bit());
+
+        self.receive(buffer)
+    }
+}
+
+impl<'a, S, R> hal::blocking::spi::Write<u8> for Spi<'a, S, R, Bidirectional>
+    where S : Any + SPI, R : IsRemapped {
+    type Error = Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        Spi::write(self, words)
+    }
+}
+
+impl<'a, S, R> Spi<'a, S, R, ReceiveOnly>
+where S: Any + SPI, R: IsRemapped,
+{
+    /// Initializes the SPI module as a master which only receives data
+    pub fn new_receive_only(spi: &'a S, _ports: SpiBusPorts<S, R, ReceiveOnly>, _rcc_periph: RccPeripheral<S, rcc::Enabled>, 
+        mode: hal::spi::Mode, freq: Hertz, clocks: Clocks) -> Self {
+
+        spi.cr2.modify(|_, w| { w.ssoe().clear_bit() });
+
+        let br = baud_rate_bits(S::clock(&clocks), freq);
+
+        spi.cr1.write(|w| {
+            w.cpha()
+            .bit(mode.phase == hal::spi::Phase::CaptureOnSecondTransition)
+            .cpol()
+            .bit(mode.polarity == hal::spi::Polarity::IdleHigh)
+            .mstr()
+            .set_bit()
+            .br()
+            .bits(br)
+            .lsbfirst()
+            .clear_bit()
+            .ssm()
+            .set_bit()
+            .ssi()
+            .set_bit()
+            .rxonly()
+            .set_bit()
+            .dff()
+            .clear_bit()
+            .bidimode()
+            .clear_bit()
+        });
+
+        Spi(spi, PhantomData, apb_ratio::<S>(&clocks))
+    }
+
+    /// Fills the buffer with received bytes
+    /// 
+    /// The clock only runs during the reception, the bus does not have to be enabled.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        self.disable();
+        self.receive(buffer)
+    }
+}
+
+impl<'a, S, R, M> Spi<'a, S, R, M>
+where S: Any + SPI, R: IsRemapped, M: SpiMode,
+{
+    /// Receives `buffer.len()` bytes in one of the receive only modes
+    /// 
+    /// The clock starts once the bus is enabled and keeps running until it is 
+    /// disabled, which has to happen during the last frame: after the second to
+    /// last byte has been received the bus is disabled one clock cycle later, so 
+    /// that the last frame is completed and no further frame is started.
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let n = buffer.len();
+        if n == 0 {
+            return Ok(());
+        }
+
+        self.enable();
+        if n == 1 {
+            self.wait_clock_cycle();
+            self.disable();
+        }
+
+        let mut res = Ok(());
+        for (i, b) in buffer.iter_mut().enumerate() {
+            loop {
+                let sr = self.0.sr.read();
+                if sr.ovr().bit_is_set() {
+                    res = Err(Error::Overrun);
+                }
+                if sr.rxne().bit_is_set() {
+                    break;
+                }
+            }
+
+            *b = unsafe { ptr::read_volatile(&self.0.dr as *const _ as *const u8) };
+
+            if i + 2 == n {
+                self.wait_clock_cycle();
+                self.disable();
+            }
+        }
+
+        // wait until the last frame has been received before the bus may be reconfigured
+        self.wait_idle();
+
+        if res.is_err() {
+            // clear the overrun flag
+            self.0.sr.read();
+        }
+
+        res
+    }
+
+    /// Busy waits for one cycle of the bus clock
+    fn wait_clock_cycle(&self) {
+        let br = self.0.cr1.read().br().bits() as u32;
+        asm::delay(self.2 * (2 << br));
+    }
+}
+
+impl<'a, S, R, M> Spi<'a, S, R, M>
+where S: Any + SPI, R: IsRemapped, M: SpiMode,
+{
     pub fn listen(&self, tx : bool, rx : bool) {
         let spi = self.0;
 
@@ -166,6 +738,13 @@ where S: Any + SPI, R: IsRemapped,
             w.errie().set_bit() });
     }
 
+    /// Enables DMA requests for transmission and / or reception
+    /// 
+    /// The DMA channels themselves have to be configured separately.
+    pub fn listen_dma(&self, tx : bool, rx : bool) {
+        self.0.cr2.modify(|_, w| w.txdmaen().bit(tx).rxdmaen().bit(rx));
+    }
+
     /// Disables the SPI bus
     ///
     /// **NOTE** This drives the NSS pin high
@@ -179,10 +758,15 @@ where S: Any + SPI, R: IsRemapped,
     pub fn enable(&self) {
         self.0.cr1.modify(|_, w| w.spe().set_bit())
     }
+
+    /// Waits until the current frame has been shifted out completely
+    pub fn wait_idle(&self) {
+        while self.0.sr.read().bsy().bit_is_set() {}
+    }
 }
 
-impl<'a, S, R> hal::spi::FullDuplex<u8> for Spi<'a, S, R>
-    where S : Any + SPI, R : IsRemapped {
+impl<'a, S, R, M> hal::spi::FullDuplex<u8> for Spi<'a, S, R, M>
+    where S : Any + SPI, R : IsRemapped, M : SpiMode + FullDuplexMode {
         type Error = Error;
 
     fn read(&mut self) -> nb::Result<u8, Error> {
@@ -212,11 +796,301 @@ impl<'a, S, R> hal::spi::FullDuplex<u8> for Spi<'a, S, R>
             nb::Error::Other(Error::ModeFault)
         } else if sr.crcerr().bit_is_set() {
             nb::Error::Other(Error::CrcError)
-        } else if sr.rxne().bit_is_set() {
-            unsafe { ptr::write_volatile(&self.0.dr as * const _ as *mut u8, byte) }
+        } else if sr.txe().bit_is_set() {
+            unsafe { common::write_register(&self.0.dr, byte) }
+            return Ok(());
+        } else {
+            nb::Error::WouldBlock
+        })
+    }
+}
+
+impl<'a, S, R> hal::blocking::spi::transfer::Default<u8> for Spi<'a, S, R, Master>
+    where S : Any + SPI, R : IsRemapped {}
+
+impl<'a, S, R> hal::blocking::spi::write::Default<u8> for Spi<'a, S, R, Master>
+    where S : Any + SPI, R : IsRemapped {}
+
+impl<'a, S, R, M> Spi<'a, S, R, M>
+where S: Any + SPI, R: IsRemapped, M: SpiMode + FullDuplexMode {
+    /// Enables the hardware CRC calculation using the given polynomial
+    /// 
+    /// As the bus uses 8 bit frames, the CRC is 8 bits wide. The CRC is transmitted and
+    /// checked automatically by `transfer_crc` and `write_crc`.
+    pub fn enable_crc(&mut self, polynomial: u8) {
+        self.modify_crc(|spi| {
+            spi.crcpr.write(|w| unsafe { w.crcpoly().bits(polynomial as u16) });
+            spi.cr1.modify(|_, w| w.crcen().set_bit());
+        });
+    }
+
+    /// Disables the hardware CRC calculation
+    pub fn disable_crc(&mut self) {
+        self.modify_crc(|spi| spi.cr1.modify(|_, w| w.crcen().clear_bit()));
+    }
+
+    /// Clears the CRC registers, starting the calculation of a new block
+    pub fn reset_crc(&mut self) {
+        self.modify_crc(|spi| {
+            spi.cr1.modify(|_, w| w.crcen().clear_bit());
+            spi.cr1.modify(|_, w| w.crcen().set_bit());
+        });
+    }
+
+    /// Disables the bus while `f` changes the configuration, the bus is enabled
+    /// again afterwards only if it has been enabled before
+    fn modify_crc<F>(&mut self, f: F)
+        where F: FnOnce(&S) {
+        let enabled = self.0.cr1.read().spe().bit_is_set();
+        if enabled {
+            self.wait_idle();
+            self.disable();
+        }
+
+        f(self.0);
+
+        if enabled {
+            self.enable();
+        }
+    }
+
+    /// Returns the CRC of the bytes received since the last reset
+    pub fn rx_crc(&self) -> u8 {
+        self.0.rxcrcr.read().bits() as u8
+    }
+
+    /// Returns the CRC of the bytes transmitted since the last reset
+    pub fn tx_crc(&self) -> u8 {
+        self.0.txcrcr.read().bits() as u8
+    }
+
+    /// Transfers a CRC protected block
+    /// 
+    /// The CRC of the transmitted bytes is appended to the block and the CRC received 
+    /// from the other side is compared against the CRC of the received bytes. Returns
+    /// `Err(Error::CrcError)` if they do not match. CRC calculation has to be enabled 
+    /// using `enable_crc`. Nothing is transferred for an empty block.
+    pub fn transfer_crc<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Error> {
+        if words.is_empty() {
+            return Ok(words);
+        }
+        self.reset_crc();
+
+        let last = words.len() - 1;
+        for (i, word) in words.iter_mut().enumerate() {
+            block!(hal::spi::FullDuplex::send(self, *word))?;
+            if i == last {
+                self.0.cr1.modify(|_, w| w.crcnext().set_bit());
+            }
+            *word = block!(hal::spi::FullDuplex::read(self))?;
+        }
+
+        self.finish_crc()?;
+
+        Ok(words)
+    }
+
+    /// Writes a CRC protected block
+    /// 
+    /// The CRC of the transmitted bytes is appended to the block. The received bytes
+    /// are discarded, but the received CRC is still checked.
+    pub fn write_crc(&mut self, words: &[u8]) -> Result<(), Error> {
+        if words.is_empty() {
+            return Ok(());
+        }
+        self.reset_crc();
+
+        let last = words.len() - 1;
+        for (i, word) in words.iter().enumerate() {
+            block!(hal::spi::FullDuplex::send(self, *word))?;
+            if i == last {
+                self.0.cr1.modify(|_, w| w.crcnext().set_bit());
+            }
+            block!(hal::spi::FullDuplex::read(self))?;
+        }
+
+        self.finish_crc()
+    }
+
+    /// Receives the CRC following the block and checks the `CRCERR` flag
+    fn finish_crc(&mut self) -> Result<(), Error> {
+        // the received CRC is stored in the data register, read it even if
+        // the CRC did not match to clear RXNE
+        while self.0.sr.read().rxne().bit_is_clear() {}
+        unsafe { ptr::read_volatile(&self.0.dr as *const _ as *const u8) };
+        self.wait_idle();
+
+        let sr = self.0.sr.read();
+        if sr.crcerr().bit_is_set() {
+            self.0.sr.modify(|_, w| w.crcerr().clear_bit());
+            Err(Error::CrcError)
+        } else if sr.ovr().bit_is_set() {
+            Err(Error::Overrun)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Byte shifted out while receiving using DMA
+static DMA_DUMMY: u8 = 0xFF;
+
+/// SPI bus with the DMA channels used for reception and transmission
+pub struct SpiDma<'a, S, R, M>
+where S: Any + SPI, R: IsRemapped, M: SpiMode {
+    spi: Spi<'a, S, R, M>,
+    rx: DmaChannel<S::DmaRx, S::Dma>,
+    tx: DmaChannel<S::DmaTx, S::Dma>,
+}
+
+/// An ongoing DMA transfer, which owns the buffer `B` until it has completed
+pub struct SpiDmaTransfer<'a, S, R, M, B>
+where S: Any + SPI, R: IsRemapped, M: SpiMode {
+    dma: SpiDma<'a, S, R, M>,
+    buffer: B,
+    receive: bool,
+}
+
+impl<'a, S, R, M> Spi<'a, S, R, M>
+where S: Any + SPI, R: IsRemapped, M: SpiMode + FullDuplexMode {
+    /// Uses the given DMA channels for transfers
+    /// 
+    /// SPI1 uses DMA1 channels 2 (RX) and 3 (TX), SPI2 uses DMA1 channels 4 (RX) and 5 (TX)
+    /// and SPI3 uses DMA2 channels 1 (RX) and 2 (TX).
+    pub fn with_dma(self, rx: DmaChannel<S::DmaRx, S::Dma>, tx: DmaChannel<S::DmaTx, S::Dma>) -> SpiDma<'a, S, R, M> {
+        SpiDma {
+            spi: self,
+            rx,
+            tx,
+        }
+    }
+}
+
+impl<'a, S, R, M> SpiDma<'a, S, R, M>
+where S: Any + SPI, R: IsRemapped, M: SpiMode {
+    /// Releases the `Spi` and the DMA channels
+    pub fn free(self) -> (Spi<'a, S, R, M>, DmaChannel<S::DmaRx, S::Dma>, DmaChannel<S::DmaTx, S::Dma>) {
+        (self.spi, self.rx, self.tx)
+    }
+
+    #[inline(always)]
+    fn dr(&self) -> u32 {
+        &self.spi.0.dr as *const _ as u32
+    }
+
+    /// Transmits the buffer, discarding any received bytes
+    pub fn write(mut self, buffer: &'static [u8]) -> SpiDmaTransfer<'a, S, R, M, &'static [u8]> {
+        let dr = self.dr();
+
+        atomic::compiler_fence(Ordering::Release);
+
+        self.spi.listen_dma(false, false);
+        self.tx.start(dr, buffer.as_ptr() as u32, buffer.len(), dma::Direction::MemoryToPeripheral, true);
+        self.spi.listen_dma(true, false);
+        self.spi.enable();
+
+        SpiDmaTransfer {
+            dma: self,
+            buffer,
+            receive: false,
+        }
+    }
+
+    /// Fills the buffer with received bytes, transmitting `0xFF` for each byte
+    pub fn read(mut self, buffer: &'static mut [u8]) -> SpiDmaTransfer<'a, S, R, M, &'static mut [u8]> {
+        let dr = self.dr();
+
+        atomic::compiler_fence(Ordering::Release);
+
+        self.spi.listen_dma(false, false);
+        self.rx.start(dr, buffer.as_mut_ptr() as u32, buffer.len(), dma::Direction::PeripheralToMemory, true);
+        self.tx.start(dr, &DMA_DUMMY as *const u8 as u32, buffer.len(), dma::Direction::MemoryToPeripheral, false);
+        self.spi.listen_dma(true, true);
+        self.spi.enable();
+
+        SpiDmaTransfer {
+            dma: self,
+            buffer,
+            receive: true,
+        }
+    }
+
+    /// Transmits `tx` while storing the received bytes in `rx`
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if the buffers do not have the same length.
+    pub fn transfer(mut self, tx: &'static [u8], rx: &'static mut [u8]) 
+        -> SpiDmaTransfer<'a, S, R, M, (&'static [u8], &'static mut [u8])> {
+        assert!(tx.len() == rx.len());
+
+        let dr = self.dr();
+
+        atomic::compiler_fence(Ordering::Release);
+
+        self.spi.listen_dma(false, false);
+        self.rx.start(dr, rx.as_mut_ptr() as u32, rx.len(), dma::Direction::PeripheralToMemory, true);
+        self.tx.start(dr, tx.as_ptr() as u32, tx.len(), dma::Direction::MemoryToPeripheral, true);
+        self.spi.listen_dma(true, true);
+        self.spi.enable();
+
+        SpiDmaTransfer {
+            dma: self,
+            buffer: (tx, rx),
+            receive: true,
+        }
+    }
+}
+
+impl<'a, S, R, M, B> SpiDmaTransfer<'a, S, R, M, B>
+where S: Any + SPI, R: IsRemapped, M: SpiMode {
+    fn check(&self) -> nb::Result<(), Error> {
+        let dma = &self.dma;
+        let sr = dma.spi.0.sr.read();
+
+        let done = if self.receive {
+            dma.rx.is_complete()
+        } else {
+            dma.tx.is_complete() && sr.txe().bit_is_set() && sr.bsy().bit_is_clear()
+        };
+
+        Err(if dma.rx.has_error() || dma.tx.has_error() {
+            nb::Error::Other(Error::TransferError)
+        } else if self.receive && sr.ovr().bit_is_set() {
+            nb::Error::Other(Error::Overrun)
+        } else if sr.modf().bit_is_set() {
+            nb::Error::Other(Error::ModeFault)
+        } else if done {
             return Ok(());
         } else {
             nb::Error::WouldBlock
         })
     }
+
+    /// Returns `true` once the transfer has finished, either successfully or with an error
+    pub fn is_done(&self) -> bool {
+        !matches!(self.check(), Err(nb::Error::WouldBlock))
+    }
+
+    /// Waits for the transfer to finish and returns the buffer and the bus
+    /// 
+    /// The result indicates whether the transfer was successful.
+    pub fn wait(mut self) -> (Result<(), Error>, B, SpiDma<'a, S, R, M>) {
+        let res = block!(self.check());
+
+        self.dma.spi.wait_idle();
+        self.dma.spi.listen_dma(false, false);
+        self.dma.rx.stop();
+        self.dma.tx.stop();
+
+        if !self.receive {
+            // discard the bytes received during transmission and clear the overrun flag
+            unsafe { ptr::read_volatile(&self.dma.spi.0.dr as *const _ as *const u8) };
+            self.dma.spi.0.sr.read();
+        }
+
+        atomic::compiler_fence(Ordering::Acquire);
+
+        (res, self.buffer, self.dma)
+    }
 }
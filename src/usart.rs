@@ -163,7 +163,7 @@ impl<U, R> hal::serial::Read<u8> for UsartRx<U, R>
         } else if sr.ore().bit_is_set() {
             nb::Error::Other(Error::Overrun)
         } else if sr.rxne().bit_is_set() {
-            // NOTE(read_volatile) see `write_volatile` below
+            // NOTE(read_volatile) see `write_register` below
             return Ok(unsafe {
                 ptr::read_volatile(&(*U::ptr()).dr as *const _ as *const _)
             });
@@ -192,7 +192,7 @@ impl<U, R> hal::serial::Write<u8> for UsartTx<U, R>
 
             if sr.txe().bit_is_set() {
                 unsafe {
-                    ptr::write_volatile(&(*U::ptr()).dr as *const _ as *mut _, byte)
+                    common::write_register(&(*U::ptr()).dr, byte)
                 }
                 Ok(())
             } else {